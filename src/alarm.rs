@@ -0,0 +1,214 @@
+use crate::common::{Label, Value};
+use crate::message::{AlarmNotification, AlarmState};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum AlarmLevel {
+    HighHigh,
+    High,
+    Low,
+    LowLow,
+}
+
+/// Limits for one numeric label. A limit trips when the value reaches it and
+/// clears once the value has moved back past it by `hysteresis`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct AlarmRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_high: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_low: Option<f64>,
+    #[serde(default)]
+    pub hysteresis: f64,
+}
+
+impl AlarmRule {
+    fn level(&self, value: f64, current: Option<AlarmLevel>) -> Option<AlarmLevel> {
+        use AlarmLevel::*;
+        let hysteresis = self.hysteresis;
+        let above = |limit: Option<f64>, latched: bool| {
+            limit.is_some_and(|l| value >= l || (latched && value > l - hysteresis))
+        };
+        let below = |limit: Option<f64>, latched: bool| {
+            limit.is_some_and(|l| value <= l || (latched && value < l + hysteresis))
+        };
+
+        if above(self.high_high, current == Some(HighHigh)) { Some(HighHigh) }
+        else if above(self.high, matches!(current, Some(HighHigh) | Some(High))) { Some(High) }
+        else if below(self.low_low, current == Some(LowLow)) { Some(LowLow) }
+        else if below(self.low, matches!(current, Some(LowLow) | Some(Low))) { Some(Low) }
+        else { None }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ActiveAlarm {
+    pub label: Label,
+    pub level: AlarmLevel,
+    pub value: Value,
+    pub acknowledged: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct AlarmTable {
+    rules: HashMap<Label, AlarmRule>,
+    active: HashMap<Label, ActiveAlarm>,
+}
+
+impl AlarmTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_rule(&mut self, label: Label, rule: AlarmRule) {
+        self.rules.insert(label, rule);
+    }
+
+    /// Checks a newly stored value against its rule and returns a notification
+    /// when the alarm level of the label changes.
+    pub fn evaluate(&mut self, label: &str, value: &Value) -> Option<AlarmNotification> {
+        let rule = self.rules.get(label)?;
+        let number = match value {
+            Value::Int(n) => *n as f64,
+            Value::Float(f) => *f,
+            _ => return None,
+        };
+        let current = self.active.get(label).map(|alarm| alarm.level);
+
+        match (current, rule.level(number, current)) {
+            (None, None) => None,
+            (Some(level), None) => {
+                self.active.remove(label);
+                Some(AlarmNotification {
+                    label: label.to_string(),
+                    state: AlarmState::Cleared,
+                    level,
+                    value: value.clone(),
+                })
+            }
+            (current, Some(level)) => {
+                if let Some(alarm) = self.active.get_mut(label) {
+                    alarm.value = value.clone();
+                }
+                if current == Some(level) {
+                    return None;
+                }
+                self.active.insert(label.to_string(), ActiveAlarm {
+                    label: label.to_string(),
+                    level,
+                    value: value.clone(),
+                    acknowledged: false,
+                });
+                Some(AlarmNotification {
+                    label: label.to_string(),
+                    state: AlarmState::Active,
+                    level,
+                    value: value.clone(),
+                })
+            }
+        }
+    }
+
+    /// Marks the active alarm of `label` as acknowledged. Returns false when
+    /// the label has no active alarm.
+    pub fn acknowledge(&mut self, label: &str) -> bool {
+        match self.active.get_mut(label) {
+            Some(alarm) => {
+                alarm.acknowledged = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn active_alarms(&self) -> Vec<ActiveAlarm> {
+        let mut alarms: Vec<ActiveAlarm> = self.active.values().cloned().collect();
+        alarms.sort_by(|a, b| a.label.cmp(&b.label));
+        alarms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> AlarmTable {
+        let mut table = AlarmTable::new();
+        table.set_rule("TI1".to_string(), AlarmRule {
+            high_high: Some(90.0),
+            high: Some(80.0),
+            low: Some(20.0),
+            low_low: Some(10.0),
+            hysteresis: 2.0,
+        });
+        table
+    }
+
+    #[test]
+    fn test_alarm_trip_and_clear_with_hysteresis() {
+        let mut table = table();
+
+        assert_eq!(table.evaluate("TI1", &Value::Float(50.0)), None);
+
+        let n = table.evaluate("TI1", &Value::Float(80.0)).unwrap();
+        assert_eq!(n.state, AlarmState::Active);
+        assert_eq!(n.level, AlarmLevel::High);
+
+        // still inside the hysteresis band
+        assert_eq!(table.evaluate("TI1", &Value::Float(78.5)), None);
+        assert_eq!(table.active_alarms()[0].value, Value::Float(78.5));
+
+        let n = table.evaluate("TI1", &Value::Float(77.9)).unwrap();
+        assert_eq!(n.state, AlarmState::Cleared);
+        assert_eq!(n.level, AlarmLevel::High);
+        assert!(table.active_alarms().is_empty());
+    }
+
+    #[test]
+    fn test_alarm_level_change() {
+        let mut table = table();
+
+        let n = table.evaluate("TI1", &Value::Int(95)).unwrap();
+        assert_eq!(n.level, AlarmLevel::HighHigh);
+
+        let n = table.evaluate("TI1", &Value::Int(85)).unwrap();
+        assert_eq!(n.state, AlarmState::Active);
+        assert_eq!(n.level, AlarmLevel::High);
+
+        let n = table.evaluate("TI1", &Value::Int(5)).unwrap();
+        assert_eq!(n.level, AlarmLevel::LowLow);
+
+        assert_eq!(table.evaluate("TI1", &Value::Int(11)), None);
+
+        let n = table.evaluate("TI1", &Value::Int(15)).unwrap();
+        assert_eq!(n.level, AlarmLevel::Low);
+    }
+
+    #[test]
+    fn test_alarm_ignores_other_values() {
+        let mut table = table();
+
+        assert_eq!(table.evaluate("TI1", &Value::String("100".to_string())), None);
+        assert_eq!(table.evaluate("TI2", &Value::Float(100.0)), None);
+        assert!(table.active_alarms().is_empty());
+    }
+
+    #[test]
+    fn test_alarm_acknowledge() {
+        let mut table = table();
+
+        assert!(!table.acknowledge("TI1"));
+        table.evaluate("TI1", &Value::Float(15.0));
+        assert!(table.acknowledge("TI1"));
+        assert!(table.active_alarms()[0].acknowledged);
+
+        // a new level is unacknowledged again
+        table.evaluate("TI1", &Value::Float(5.0));
+        assert!(!table.active_alarms()[0].acknowledged);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::utils::{AppError, AppResult};
+use crate::message::LoginRequest;
+use crate::permission::ClientIdentity;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use subtle::ConstantTimeEq;
+
+/// Checked when no user has the requested name, so that unknown names cost
+/// as much time as known ones. Made by `hash_password` from a password of
+/// no user.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$u7cOZ/8gHyMesYcFK704tA$BdDDBMp77c9LiFu/Hpw25aN1uy+knfcnEyH4pQwNSPI";
+
+/// A user who logs in with name and password. Only the Argon2 hash of the
+/// password is kept, as a PHC string made by `hash_password`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct UserCredential {
+    pub name: String,
+    pub password_hash: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// A client that logs in with a token. Tokens are random rather than chosen
+/// by people, so their hex SHA-256 hash, made by `hash_token`, is enough.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TokenCredential {
+    pub token_hash: String,
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Accounts allowed to log in, usually loaded from a local JSON file.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Credentials {
+    #[serde(default)]
+    pub users: Vec<UserCredential>,
+    #[serde(default)]
+    pub tokens: Vec<TokenCredential>,
+}
+
+impl Credentials {
+    /// Fails with `Config` when the file cannot be parsed or a password hash
+    /// is not a valid PHC string.
+    pub fn load<P: AsRef<Path>>(path: P) -> AppResult<Self> {
+        let json = std::fs::read_to_string(path)?;
+        let credentials: Self = serde_json::from_str(&json).map_err(|e| AppError::Config(e.to_string()))?;
+        for user in credentials.users.iter() {
+            PasswordHash::new(&user.password_hash)
+                .map_err(|e| AppError::Config(format!("password hash of {}: {}", user.name, e)))?;
+        }
+        Ok(credentials)
+    }
+
+    /// Returns the identity of the client when the login request matches a
+    /// known token or username and password. Secrets are only compared in
+    /// constant time, against their hashes.
+    pub fn authenticate(&self, request: &LoginRequest) -> Option<ClientIdentity> {
+        if let Some(token) = &request.token {
+            let token_hash = hash_token(token);
+            return self.tokens.iter()
+                .find(|t| bool::from(t.token_hash.to_ascii_lowercase().as_bytes().ct_eq(token_hash.as_bytes())))
+                .map(|t| ClientIdentity { name: t.name.clone(), roles: t.roles.clone() });
+        }
+        let (username, password) = (request.username.as_ref()?, request.password.as_ref()?);
+        let user = self.users.iter().find(|u| &u.name == username);
+        let verified = verify_password(password, self.password_hash_of(username));
+        user.filter(|_| verified)
+            .map(|u| ClientIdentity { name: u.name.clone(), roles: u.roles.clone() })
+    }
+
+    /// Hash to check the password against, the dummy one for unknown users.
+    fn password_hash_of(&self, username: &str) -> &str {
+        self.users.iter()
+            .find(|u| u.name == username)
+            .map_or(DUMMY_PASSWORD_HASH, |u| u.password_hash.as_str())
+    }
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(password_hash) => Argon2::default().verify_password(password.as_bytes(), &password_hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Hashes a password with a new random salt, for `UserCredential`.
+pub fn hash_password(password: &str) -> AppResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Config(e.to_string()))
+}
+
+/// Hashes a token for `TokenCredential`.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(token: Option<&str>, username: Option<&str>, password: Option<&str>) -> LoginRequest {
+        LoginRequest {
+            tag: None,
+            token: token.map(str::to_string),
+            username: username.map(str::to_string),
+            password: password.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_load_and_authenticate() {
+        let json = format!(r#"
+            {{
+                "users": [{{"name": "hmi1", "password_hash": "{}", "roles": ["operator"]}}],
+                "tokens": [{{"token_hash": "{}", "name": "gateway"}}]
+            }}
+        "#, hash_password("secret").unwrap(), hash_token("T0KEN"));
+        let path = std::env::temp_dir().join("datamanager_test_credentials.json");
+        std::fs::write(&path, json).unwrap();
+
+        let credentials = Credentials::load(&path).unwrap();
+
+        let identity = credentials.authenticate(&login(None, Some("hmi1"), Some("secret"))).unwrap();
+        assert_eq!(identity.name, "hmi1");
+        assert_eq!(identity.roles, vec!["operator".to_string()]);
+
+        let identity = credentials.authenticate(&login(Some("T0KEN"), None, None)).unwrap();
+        assert_eq!(identity.name, "gateway");
+
+        assert_eq!(credentials.authenticate(&login(None, Some("hmi1"), Some("wrong"))), None);
+        assert_eq!(credentials.authenticate(&login(Some("wrong"), None, None)), None);
+        assert_eq!(credentials.authenticate(&login(None, Some("hmi1"), None)), None);
+    }
+
+    #[test]
+    fn test_unknown_user_is_verified_like_known_ones() {
+        let credentials = Credentials {
+            users: vec![UserCredential {
+                name: "hmi1".to_string(),
+                password_hash: hash_password("secret").unwrap(),
+                roles: vec![],
+            }],
+            tokens: vec![],
+        };
+
+        // the dummy hash costs as much as the hashes of real users
+        let params = |hash: &str| hash.rsplitn(3, '$').nth(2).unwrap().to_string();
+        assert_eq!(credentials.password_hash_of("nobody"), DUMMY_PASSWORD_HASH);
+        assert_eq!(params(DUMMY_PASSWORD_HASH), params(credentials.password_hash_of("hmi1")));
+        assert!(!verify_password("secret", DUMMY_PASSWORD_HASH));
+
+        assert_eq!(credentials.authenticate(&login(None, Some("nobody"), Some("secret"))), None);
+    }
+
+    #[test]
+    fn test_load_rejects_plaintext_passwords() {
+        let path = std::env::temp_dir().join("datamanager_test_plaintext_credentials.json");
+
+        std::fs::write(&path, r#"{"users": [{"name": "hmi1", "password": "secret"}]}"#).unwrap();
+        assert!(matches!(Credentials::load(&path), Err(AppError::Config(..))));
+
+        std::fs::write(&path, r#"{"users": [{"name": "hmi1", "password_hash": "secret"}]}"#).unwrap();
+        assert!(matches!(Credentials::load(&path), Err(AppError::Config(..))));
+    }
+}
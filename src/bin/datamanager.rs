@@ -1,9 +1,17 @@
 use async_std::prelude::*;
+use datamanager::connection;
+use datamanager::store::Store;
 use datamanager::utils::AppResult;
 
 fn main() -> AppResult<()> {
 
     async_std::task::block_on(async {
+        // With --stdio the protocol is spoken on stdin/stdout instead of a socket.
+        if std::env::args().any(|arg| arg == "--stdio") {
+            let mut store = Store::new();
+            return connection::serve_stdio(&mut store).await;
+        }
+
         use async_std::net;
         let listener = net::TcpListener::bind("localhost:8080").await?;
 
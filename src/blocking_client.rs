@@ -0,0 +1,194 @@
+use crate::client::{closed_by_server, get_request, get_result, login_request, login_result, ping_result,
+    set_request, set_result, subscribe_result, token_login_request};
+use crate::common::Value;
+use crate::codec::{Format, Framing};
+use crate::message::*;
+use crate::message_receiver::decode_message;
+use crate::utils::{AppError, AppResult, FrameTooLarge};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Synchronous counterpart of `client::Client` for programs without an
+/// async runtime. It has the same methods and returns the same errors.
+pub struct BlockingClient {
+    to_server: TcpStream,
+    from_server: BufReader<TcpStream>,
+    format: Format,
+    notifications: VecDeque<AlarmNotification>,
+}
+
+impl BlockingClient {
+    pub fn connect<A: ToSocketAddrs>(addrs: A) -> AppResult<Self> {
+        Self::with_format(TcpStream::connect(addrs)?, Format::default())
+    }
+
+    pub fn with_format(stream: TcpStream, format: Format) -> AppResult<Self> {
+        Ok(Self {
+            from_server: BufReader::new(stream.try_clone()?),
+            to_server: stream,
+            format,
+            notifications: VecDeque::new(),
+        })
+    }
+
+    pub fn login(&mut self, username: &str, password: &str) -> AppResult<()> {
+        let response = self.request(login_request(username, password))?;
+        login_result(response)
+    }
+
+    pub fn login_with_token(&mut self, token: &str) -> AppResult<()> {
+        let response = self.request(token_login_request(token))?;
+        login_result(response)
+    }
+
+    /// Returns one result per label. Labels the server does not know come
+    /// back with a `NotFound` status instead of failing the whole call.
+    pub fn get(&mut self, labels: &[&str]) -> AppResult<Vec<LabeledValue>> {
+        let response = self.request(get_request(labels))?;
+        get_result(response)
+    }
+
+    /// Writes all values, or none of them when the request is rejected.
+    pub fn set(&mut self, values: &[(&str, Value)]) -> AppResult<()> {
+        let response = self.request(set_request(values))?;
+        set_result(response)
+    }
+
+    /// Subscribes to alarm notifications, which are then read with
+    /// `next_notification`.
+    pub fn subscribe(&mut self) -> AppResult<()> {
+        let response = self.request(Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag: None }))?;
+        subscribe_result(response)
+    }
+
+    /// Blocks until the next alarm notification arrives.
+    pub fn next_notification(&mut self) -> AppResult<AlarmNotification> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(notification);
+        }
+        loop {
+            if let Message::AlarmNotification(notification) = self.receive()? {
+                return Ok(notification);
+            }
+        }
+    }
+
+    /// Keeps the connection alive past the server's idle timeout.
+    pub fn ping(&mut self) -> AppResult<()> {
+        let response = self.request(Message::Ping(Ping { tag: None }))?;
+        ping_result(response)
+    }
+
+    fn request(&mut self, message: Message) -> AppResult<Message> {
+        self.send(&message)?;
+        loop {
+            match self.receive()? {
+                Message::AlarmNotification(notification) => self.notifications.push_back(notification),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    fn send(&mut self, message: &Message) -> AppResult<()> {
+        let mut frame = self.format.codec.encode(message)?;
+        match self.format.framing {
+            Framing::Lines => {
+                frame.push(b'\n');
+                self.to_server.write_all(&frame)?;
+            }
+            Framing::LengthPrefixed => {
+                let len = u32::try_from(frame.len()).map_err(|_| FrameTooLarge { limit: u32::MAX as usize })?;
+                self.to_server.write_all(&len.to_be_bytes())?;
+                self.to_server.write_all(&frame)?;
+            }
+        }
+        self.to_server.flush()?;
+        Ok(())
+    }
+
+    /// Reads one message. Oversized input is skipped and reported as
+    /// `FrameTooLarge`, like on the async receive path.
+    fn receive(&mut self) -> AppResult<Message> {
+        let limit = self.format.max_frame_size;
+        let frame = match self.format.framing {
+            Framing::Lines => {
+                let mut line = Vec::new();
+                let read = (&mut self.from_server).take(limit as u64 + 1).read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    return Err(closed_by_server());
+                }
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                } else if line.len() > limit {
+                    self.from_server.skip_until(b'\n')?;
+                    return Err(FrameTooLarge { limit }.into());
+                }
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                line
+            }
+            Framing::LengthPrefixed => {
+                let mut len = [0u8; 4];
+                self.from_server.read_exact(&mut len).map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => closed_by_server(),
+                    _ => AppError::from(e),
+                })?;
+                let len = u32::from_be_bytes(len) as usize;
+                if len > limit {
+                    io::copy(&mut (&mut self.from_server).take(len as u64), &mut io::sink())?;
+                    return Err(FrameTooLarge { limit }.into());
+                }
+                let mut frame = vec![0u8; len];
+                self.from_server.read_exact(&mut frame)?;
+                frame
+            }
+        };
+        decode_message(self.format.codec, &frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmLevel, AlarmRule};
+    use crate::codec::Codec;
+    use crate::connection;
+    use crate::store::Store;
+    use async_std::prelude::*;
+    use async_std::{net, task};
+
+    #[test]
+    fn test_blocking_client() {
+        let listener = std::net::TcpListener::bind("localhost:8904").unwrap();
+
+        // server
+        std::thread::spawn(move || task::block_on(async {
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule { high: Some(80.0), ..Default::default() });
+            let options = connection::ServeOptions { format: Format::new(Codec::Cbor), ..Default::default() };
+            let listener = net::TcpListener::from(listener);
+            let mut new_connections = listener.incoming();
+            while let Some(socket_result) = new_connections.next().await {
+                connection::serve_with(socket_result?, &mut store, &options).await?;
+            }
+            Ok(()) as AppResult<()>
+        }));
+
+        // client
+        let stream = TcpStream::connect("localhost:8904").unwrap();
+        let mut client = BlockingClient::with_format(stream, Format::new(Codec::Cbor)).unwrap();
+
+        client.set(&[("SP1", Value::Float(3.0))]).unwrap();
+        let results = client.get(&["SP1", "NE1"]).unwrap();
+        assert_eq!(results[0].value, Value::Float(3.0));
+        assert_eq!(results[1].status, Some(Status::NotFound));
+
+        client.subscribe().unwrap();
+        client.set(&[("TI1", Value::Float(85.0))]).unwrap();
+        client.ping().unwrap();
+        let notification = client.next_notification().unwrap();
+        assert_eq!(notification.level, AlarmLevel::High);
+    }
+}
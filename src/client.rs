@@ -0,0 +1,534 @@
+use crate::common::{Label, Value};
+use crate::codec::Format;
+use crate::heartbeat;
+use crate::message::*;
+use crate::message_receiver::{receive_messages, Outbound};
+use crate::utils::{AppError, AppResult, FrameTooLarge, MalformedMessage};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::net::{TcpStream, ToSocketAddrs};
+use async_std::prelude::*;
+use async_std::task::{self, JoinHandle};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Responses still awaited, by tag. None once the connection is closed.
+type Waiting = Arc<Mutex<Option<HashMap<String, Sender<AppResult<Message>>>>>>;
+
+/// Connection to a datamanager server. Every request is tagged, so many can
+/// be in flight at once: a reader task hands each response to the request
+/// with the same tag and queues alarm notifications for
+/// `next_notification`.
+pub struct Client {
+    outbound: Outbound,
+    waiting: Waiting,
+    next_tag: AtomicU64,
+    notifications: Receiver<AlarmNotification>,
+    reader: Option<JoinHandle<()>>,
+    keepalive: Option<JoinHandle<()>>,
+}
+
+impl Client {
+    pub async fn connect<A: ToSocketAddrs>(addrs: A) -> AppResult<Self> {
+        Ok(Self::new(TcpStream::connect(addrs).await?))
+    }
+
+    /// Uses an already open stream, for example a TLS or unix socket.
+    pub fn new<T>(stream: T) -> Self
+    where
+        T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
+    {
+        Self::with_format(stream, Format::default())
+    }
+
+    pub fn with_format<T>(stream: T, format: Format) -> Self
+    where
+        T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
+    {
+        let waiting: Waiting = Arc::new(Mutex::new(Some(HashMap::new())));
+        let (notify, notifications) = channel::unbounded();
+        let reader = task::spawn(route_replies(receive_messages(stream.clone(), format), waiting.clone(), notify));
+        Self {
+            outbound: Outbound::with_format(stream, format),
+            waiting,
+            next_tag: AtomicU64::new(1),
+            notifications,
+            reader: Some(reader),
+            keepalive: None,
+        }
+    }
+
+    /// Pings the server every `interval` from a background task until the
+    /// client is dropped, so that the server's idle timeout does not close
+    /// a connection that is only waiting for notifications.
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        let outbound = self.outbound.clone();
+        if let Some(keepalive) = self.keepalive.take() {
+            task::spawn(keepalive.cancel());
+        }
+        self.keepalive = Some(task::spawn(async move {
+            let _ = heartbeat::keepalive(&outbound, interval).await;
+        }));
+        self
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> AppResult<()> {
+        let response = self.request(login_request(username, password)).await?;
+        login_result(response)
+    }
+
+    pub async fn login_with_token(&self, token: &str) -> AppResult<()> {
+        let response = self.request(token_login_request(token)).await?;
+        login_result(response)
+    }
+
+    /// Returns one result per label. Labels the server does not know come
+    /// back with a `NotFound` status instead of failing the whole call.
+    pub async fn get(&self, labels: &[&str]) -> AppResult<Vec<LabeledValue>> {
+        let response = self.request(get_request(labels)).await?;
+        get_result(response)
+    }
+
+    /// Writes all values, or none of them when the request is rejected.
+    pub async fn set(&self, values: &[(&str, Value)]) -> AppResult<()> {
+        let response = self.request(set_request(values)).await?;
+        set_result(response)
+    }
+
+    /// Subscribes to alarm notifications, which are then read with
+    /// `next_notification`.
+    pub async fn subscribe(&self) -> AppResult<()> {
+        let response = self.request(Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag: None })).await?;
+        subscribe_result(response)
+    }
+
+    pub async fn next_notification(&self) -> AppResult<AlarmNotification> {
+        self.notifications.recv().await.map_err(|_| closed_by_server())
+    }
+
+    /// Keeps the connection alive past the server's idle timeout.
+    pub async fn ping(&self) -> AppResult<()> {
+        let response = self.request(Message::Ping(Ping { tag: None })).await?;
+        ping_result(response)
+    }
+
+    async fn request(&self, mut message: Message) -> AppResult<Message> {
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed).to_string();
+        if let Some(request_tag) = message.tag_mut() {
+            *request_tag = Some(tag.clone());
+        }
+        let (reply, response) = channel::bounded(1);
+        match self.waiting.lock().unwrap().as_mut() {
+            Some(waiting) => waiting.insert(tag.clone(), reply),
+            None => return Err(closed_by_server()),
+        };
+        let _forget = Forget { waiting: &self.waiting, tag: &tag };
+        self.outbound.send(&message).await?;
+        response.recv().await.map_err(|_| closed_by_server())?
+    }
+}
+
+/// Removes a request from `waiting` when it fails or its future is dropped.
+struct Forget<'a> {
+    waiting: &'a Waiting,
+    tag: &'a str,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        if let Some(waiting) = self.waiting.lock().unwrap().as_mut() {
+            waiting.remove(self.tag);
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            task::spawn(reader.cancel());
+        }
+        if let Some(keepalive) = self.keepalive.take() {
+            task::spawn(keepalive.cancel());
+        }
+    }
+}
+
+/// Reads until the connection ends, then fails the requests still waiting.
+/// Errors that cannot be matched to a request by tag, such as the server
+/// rejecting an oversized request, fail every request in flight, since any
+/// of them may be the one that caused it.
+async fn route_replies(
+    mut inbound: Pin<Box<dyn Stream<Item = AppResult<Message>> + Send>>,
+    waiting: Waiting,
+    notify: Sender<AlarmNotification>,
+) {
+    while let Some(message_result) = inbound.next().await {
+        match message_result {
+            Ok(Message::AlarmNotification(notification)) => {
+                let _ = notify.send(notification).await;
+            }
+            Ok(Message::ErrorResponse(ErrorResponse { tag: None, status, reason })) => {
+                fail_all(&waiting, || AppError::Rejected { status, reason: Some(reason.clone()) });
+            }
+            // other untagged replies, such as pongs to a keepalive, have no one waiting
+            Ok(response) => {
+                if let Some(tag) = response.tag().cloned() {
+                    reply(&waiting, &tag, Ok(response));
+                }
+            }
+            Err(AppError::Decode(MalformedMessage { tag: Some(tag), reason })) => {
+                let error = MalformedMessage { tag: Some(tag.clone()), reason };
+                reply(&waiting, &tag, Err(error.into()));
+            }
+            Err(AppError::Decode(MalformedMessage { tag: None, reason })) => {
+                fail_all(&waiting, || MalformedMessage { tag: None, reason: reason.clone() }.into());
+            }
+            Err(AppError::FrameTooLarge(FrameTooLarge { limit })) => {
+                fail_all(&waiting, || FrameTooLarge { limit }.into());
+            }
+            Err(_) => break,
+        }
+    }
+    waiting.lock().unwrap().take();
+}
+
+fn reply(waiting: &Waiting, tag: &str, result: AppResult<Message>) {
+    if let Some(reply) = waiting.lock().unwrap().as_mut().and_then(|waiting| waiting.remove(tag)) {
+        let _ = reply.try_send(result);
+    }
+}
+
+fn fail_all(waiting: &Waiting, error: impl Fn() -> AppError) {
+    if let Some(waiting) = waiting.lock().unwrap().as_mut() {
+        for (_, reply) in waiting.drain() {
+            let _ = reply.try_send(Err(error()));
+        }
+    }
+}
+
+// Requests and replies shared with the blocking client.
+
+pub(crate) fn login_request(username: &str, password: &str) -> Message {
+    Message::LoginRequest(LoginRequest {
+        tag: None,
+        token: None,
+        username: Some(username.to_string()),
+        password: Some(password.to_string()),
+    })
+}
+
+pub(crate) fn token_login_request(token: &str) -> Message {
+    Message::LoginRequest(LoginRequest {
+        tag: None,
+        token: Some(token.to_string()),
+        username: None,
+        password: None,
+    })
+}
+
+pub(crate) fn get_request(labels: &[&str]) -> Message {
+    Message::GetDataRequest(GetDataRequest {
+        tag: None,
+        params: labels.iter().map(|label| label.to_string()).collect(),
+    })
+}
+
+pub(crate) fn set_request(values: &[(&str, Value)]) -> Message {
+    Message::SetDataRequest(SetDataRequest {
+        tag: None,
+        params: values.iter()
+            .map(|(label, value)| LabeledValue::new(Label::from(*label), value.clone()))
+            .collect(),
+    })
+}
+
+pub(crate) fn login_result(response: Message) -> AppResult<()> {
+    match response {
+        Message::LoginResponse(r) => check(r.status),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(crate) fn get_result(response: Message) -> AppResult<Vec<LabeledValue>> {
+    match response {
+        Message::GetDataResponse(r) if matches!(r.status, Status::OK | Status::NotFound) => Ok(r.results),
+        Message::GetDataResponse(r) => check(r.status).map(|_| vec![]),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(crate) fn set_result(response: Message) -> AppResult<()> {
+    match response {
+        Message::SetDataResponse(r) => check(r.status),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(crate) fn subscribe_result(response: Message) -> AppResult<()> {
+    match response {
+        Message::SubscribeAlarmResponse(r) => check(r.status),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(crate) fn ping_result(response: Message) -> AppResult<()> {
+    match response {
+        Message::Pong(..) => Ok(()),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(crate) fn closed_by_server() -> AppError {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed by server").into()
+}
+
+fn check(status: Status) -> AppResult<()> {
+    match status {
+        Status::OK => Ok(()),
+        status => Err(AppError::Rejected { status, reason: None }),
+    }
+}
+
+fn unexpected(response: Message) -> AppError {
+    match response {
+        Message::ErrorResponse(r) => AppError::Rejected { status: r.status, reason: Some(r.reason) },
+        other => AppError::Protocol(format!("unexpected {}", other.command())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmLevel, AlarmRule};
+    use crate::connection;
+    use crate::permission::WriteAccess;
+    use crate::store::Store;
+    use async_std::net;
+
+    #[test]
+    fn test_client() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule { high: Some(80.0), ..Default::default() });
+            store.permissions.set_access("PV1".to_string(), WriteAccess::ReadOnly);
+            let listener = net::TcpListener::bind("localhost:8903").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    connection::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                let client = Client::connect("localhost:8903").await?;
+
+                client.set(&[("SP1", Value::Float(3.0))]).await?;
+                let results = client.get(&["SP1", "NE1"]).await?;
+                assert_eq!(results[0].value, Value::Float(3.0));
+                assert_eq!(results[1].status, Some(Status::NotFound));
+
+                let error = client.set(&[("PV1", Value::Int(1))]).await.unwrap_err();
+                assert!(matches!(error, AppError::Rejected { status: Status::ReadOnly, .. }));
+
+                client.subscribe().await?;
+                client.set(&[("TI1", Value::Float(85.0))]).await?;
+                client.ping().await?;
+                let notification = client.next_notification().await?;
+                assert_eq!(notification.label, "TI1");
+                assert_eq!(notification.level, AlarmLevel::High);
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_client_pipelining() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule { high: Some(80.0), ..Default::default() });
+            let listener = net::TcpListener::bind("localhost:8905").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    connection::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                let client = Arc::new(Client::connect("localhost:8905").await?);
+                client.subscribe().await?;
+
+                let requests: Vec<_> = (0..20).map(|i| {
+                    let client = client.clone();
+                    task::spawn(async move {
+                        let label = format!("SP{}", i);
+                        client.set(&[(&label, Value::Int(i))]).await?;
+                        let results = client.get(&[&label]).await?;
+                        assert_eq!(results[0].value, Value::Int(i));
+                        Ok(()) as AppResult<()>
+                    })
+                }).collect();
+                client.set(&[("TI1", Value::Float(85.0))]).await?;
+                for request in requests {
+                    request.await?;
+                }
+
+                let notification = client.next_notification().await?;
+                assert_eq!(notification.label, "TI1");
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_client_routes_responses_by_tag() {
+
+        task::block_on(async {
+
+            let listener = net::TcpListener::bind("localhost:8906").await.unwrap();
+
+            // server answering two requests in reverse order
+            let server_fut = async {
+                let (socket, _) = listener.accept().await?;
+                let mut from_client = receive_messages(socket.clone(), Format::default());
+                let to_client = Outbound::new(socket);
+                let mut tags = Vec::new();
+                for _ in 0..2 {
+                    let message = from_client.next().await.unwrap()?;
+                    tags.push(message.tag().cloned());
+                }
+                for (i, tag) in tags.into_iter().enumerate().rev() {
+                    let label = format!("SP{}", i);
+                    to_client.send(&Message::GetDataResponse(GetDataResponse {
+                        tag,
+                        status: Status::OK,
+                        results: vec![LabeledValue::new(label, Value::Int(i as i64))],
+                    })).await?;
+                }
+                // keep the connection open until the client is done
+                task::sleep(std::time::Duration::from_secs(5)).await;
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                let client = Client::connect("localhost:8906").await?;
+                let (first, second) = client.get(&["SP0"]).join(client.get(&["SP1"])).await;
+                assert_eq!(first?[0].label, "SP0");
+                assert_eq!(second?[0].label, "SP1");
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_client_fails_on_oversized_frames() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = connection::ServeOptions { format: Format::default().max_frame_size(400), ..Default::default() };
+            let listener = net::TcpListener::bind("localhost:8907").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    connection::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                let stream = TcpStream::connect("localhost:8907").await?;
+                let client = Client::with_format(stream, Format::default().max_frame_size(150));
+
+                // the server cannot tell which request was too large
+                let error = client.set(&[("SP1", Value::String("x".repeat(500)))]).await.unwrap_err();
+                assert!(matches!(error, AppError::Rejected { status: Status::InvalidRequest, .. }));
+
+                // the response is over the client's own limit
+                for label in ["SP1", "SP2", "SP3"] {
+                    client.set(&[(label, Value::String("x".repeat(30)))]).await?;
+                }
+                let error = client.get(&["SP1", "SP2", "SP3"]).await.unwrap_err();
+                assert!(matches!(error, AppError::FrameTooLarge(..)));
+
+                // a dropped request is not left waiting
+                let _ = futures_lite::future::poll_once(client.get(&["NE1"])).await;
+                assert!(client.waiting.lock().unwrap().as_ref().unwrap().is_empty());
+                client.ping().await?;
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_client_keepalive() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = connection::ServeOptions {
+                idle_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let listener = net::TcpListener::bind("localhost:8909").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    connection::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client idle for longer than the server's idle timeout
+            let client_fut = async {
+                let client = Client::connect("localhost:8909").await?
+                    .keepalive(Duration::from_millis(30));
+                task::sleep(Duration::from_millis(300)).await;
+                client.set(&[("SP1", Value::Int(1))]).await?;
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+}
@@ -0,0 +1,139 @@
+use crate::utils::{AppError, AppResult, MalformedMessage};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Encoding of messages on the wire.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Codec {
+    pub fn encode<P: Serialize>(self, packet: &P) -> AppResult<Vec<u8>> {
+        let bytes = match self {
+            Codec::Json => serde_json::to_vec(packet).map_err(|e| e.to_string()),
+            // named fields are needed for the internally tagged `Message`
+            Codec::MessagePack => rmp_serde::to_vec_named(packet).map_err(|e| e.to_string()),
+            Codec::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(packet, &mut bytes).map(|_| bytes).map_err(|e| e.to_string())
+            }
+        };
+        bytes.map_err(AppError::Encode)
+    }
+
+    pub fn decode<P: DeserializeOwned>(self, bytes: &[u8]) -> AppResult<P> {
+        let packet = match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+        };
+        packet.map_err(|reason| MalformedMessage { tag: None, reason }.into())
+    }
+}
+
+/// How messages are delimited on a stream.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Framing {
+    /// One message per line. Only usable with JSON.
+    Lines,
+    /// Every message is prefixed by its length as a big-endian u32.
+    LengthPrefixed,
+}
+
+/// Codec, framing and frame size limit used on one connection.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Format {
+    pub codec: Codec,
+    pub framing: Framing,
+    pub max_frame_size: usize,
+}
+
+impl Format {
+    /// Sends JSON one message per line and the binary codecs as
+    /// length-prefixed frames.
+    pub fn new(codec: Codec) -> Self {
+        let framing = match codec {
+            Codec::Json => Framing::Lines,
+            _ => Framing::LengthPrefixed,
+        };
+        Self { codec, framing, max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::new(Codec::Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Value;
+    use crate::message::*;
+
+    fn set_request() -> Message {
+        Message::SetDataRequest(SetDataRequest {
+            tag: Some("ABC".to_string()),
+            params: vec![
+                LabeledValue::new("SP1".to_string(), Value::Float(3.5)),
+                LabeledValue::new("NE1".to_string(), Value::Int(-10)),
+                LabeledValue::new("userName".to_string(), Value::String("murata".to_string())),
+                LabeledValue::new("NULL".to_string(), Value::Null),
+            ],
+        })
+    }
+
+    #[test]
+    fn test_message_pack_round_trip() {
+        let message = set_request();
+
+        let bytes = Codec::MessagePack.encode(&message).unwrap();
+        let decoded: Message = Codec::MessagePack.decode(&bytes).unwrap();
+
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let message = set_request();
+
+        let bytes = Codec::Cbor.encode(&message).unwrap();
+        let decoded: Message = Codec::Cbor.decode(&bytes).unwrap();
+
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_cbor_round_trip_without_tag() {
+        let message = Message::GetDataResponse(GetDataResponse {
+            tag: None,
+            status: Status::NotFound,
+            results: vec![
+                LabeledValue::new("SP1".to_string(), Value::Float(0.1)),
+                LabeledValue::new("NE1".to_string(), Value::Null),
+            ],
+        });
+
+        let bytes = Codec::Cbor.encode(&message).unwrap();
+        let decoded: Message = Codec::Cbor.decode(&bytes).unwrap();
+
+        assert_eq!(decoded, message);
+    }
+}
@@ -1,14 +1,20 @@
-use crate::common::{Label, Value};
-use crate::utils::AppResult;
+use crate::common::Value;
+use crate::utils::{AppError, AppResult, Duplex, SharedStream};
 use crate::message::*;
 use async_std::prelude::*;
+use async_std::future;
 use async_std::net::{TcpStream, TcpListener};
-use std::collections::HashMap;
+use crate::store::Store;
+use crate::dispatch::{dispatch, error_reply, Session};
+use crate::codec::{Codec, Format, Framing};
 use crate::message_receiver::*;
+use futures_rustls::TlsAcceptor;
 
 use std::pin::Pin;
+use std::time::Duration;
 use async_std::task::{Context, Poll};
 
+#[allow(dead_code)]
 struct ConnectionStream {
 
 }
@@ -20,7 +26,7 @@ impl Stream for ConnectionStream {
         let message = Message::SetDataRequest(SetDataRequest {
             tag: None,
             params: vec![
-                LabeledValue { label: "SP1".to_string(), value: Value::Float(3.0) },
+                LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
             ],
         });
         Poll::Ready(Some(Ok(message)))
@@ -34,61 +40,188 @@ where
     let listener = TcpListener::bind(addrs).await?;
     let mut new_connections = listener.incoming();
     while let Some(socket_result) = new_connections.next().await {
-        let socket = socket_result?;
+        let socket = match socket_result {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::warn!("accept failed: {}", e);
+                continue;
+            }
+        };
         let mut from_client = receive_message(socket);
         while let Some(message_result) = from_client.next().await {
-            let (message, _outbound) = message_result?;
-            let _m: Message = message;
-            println!("{:?}", _m);
+            match message_result {
+                Ok((message, _outbound)) => {
+                    let _m: Message = message;
+                    log::debug!("{:?}", _m);
+                }
+                Err(e) => {
+                    log::warn!("{}", e);
+                    // only a broken connection ends this client
+                    if error_reply(&e).is_none() {
+                        break;
+                    }
+                }
+            }
         }
     }
     Ok(())
 }
 
-pub async fn serve(socket: TcpStream, store: &mut HashMap<Label, Value>) -> AppResult<()> {
-
-    let mut from_client = receive_message(socket);
-    while let Some(message_result) = from_client.next().await {
-        let (message, outbound) = message_result?;
-        match message {
-            Message::GetDataRequest(r) => {
-                let status =
-                    if r.params.iter().all(|label| store.contains_key(label)) { Status::OK }
-                    else { Status::NotFound };
-                let results = r.params.into_iter().map(|label| LabeledValue {
-                    value: store.get(&label).unwrap_or(&Value::Null).clone(),
-                    label,
-                }).collect();
-                let response = Message::GetDataResponse(GetDataResponse {
-                    tag: r.tag,
-                    status,
-                    results,
-                });
-                outbound.send(&response).await?;
+/// Serves clients connecting to a Unix domain socket at `path`, one at a time.
+/// A socket file left over from an earlier run is replaced, but any other
+/// file at `path` is an error.
+#[cfg(unix)]
+pub async fn serve_unix<P: AsRef<std::path::Path>>(path: P, store: &mut Store) -> AppResult<()> {
+    bind_unix(path.as_ref(), None, store).await
+}
+
+/// Like `serve_unix`, and sets the permission bits of the socket file to
+/// `mode`, for example `0o660` to let only the owner and group connect.
+#[cfg(unix)]
+pub async fn serve_unix_with_mode<P: AsRef<std::path::Path>>(path: P, mode: u32, store: &mut Store) -> AppResult<()> {
+    bind_unix(path.as_ref(), Some(mode), store).await
+}
+
+#[cfg(unix)]
+async fn bind_unix(path: &std::path::Path, mode: Option<u32>, store: &mut Store) -> AppResult<()> {
+    use async_std::os::unix::net::UnixListener;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(..) => return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display())).into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path).await?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    let mut new_connections = listener.incoming();
+    while let Some(socket_result) = new_connections.next().await {
+        let result = match socket_result {
+            Ok(socket) => serve(socket, store).await,
+            Err(e) => Err(e.into()),
+        };
+        // one failing client must not stop the listener
+        if let Err(e) = result {
+            log::warn!("unix socket client failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Serves a single client speaking the protocol on stdin and stdout, for
+/// example a parent process that runs the server as a child.
+pub async fn serve_stdio(store: &mut Store) -> AppResult<()> {
+    let stdio = Duplex::new(async_std::io::stdin(), async_std::io::stdout());
+    serve(SharedStream::new(stdio), store).await
+}
+
+/// Completes the TLS handshake on an accepted socket and serves it.
+pub async fn serve_tls(socket: TcpStream, acceptor: &TlsAcceptor, store: &mut Store) -> AppResult<()> {
+    let stream = acceptor.accept(socket).await?;
+    serve(SharedStream::new(stream), store).await
+}
+
+/// Options of a listener that apply to every connection it serves.
+#[derive(Debug, Clone, Default)]
+pub struct ServeOptions {
+    pub format: Format,
+    /// Closes connections that send nothing for this long, so that dead
+    /// peers do not keep `serve` waiting forever. Clients keep the connection
+    /// alive with `Ping`.
+    pub idle_timeout: Option<Duration>,
+    /// Fails the connection when a reply cannot be written within this time,
+    /// for example because the client stopped reading.
+    pub write_timeout: Option<Duration>,
+    /// Closes connections after this long, however active they are.
+    pub connection_timeout: Option<Duration>,
+}
+
+pub async fn serve<T>(socket: T, store: &mut Store) -> AppResult<()>
+where
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
+{
+    serve_with(socket, store, &ServeOptions::default()).await
+}
+
+pub async fn serve_with<T>(socket: T, store: &mut Store, options: &ServeOptions) -> AppResult<()>
+where
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
+{
+    if options.format.framing == Framing::Lines && options.format.codec != Codec::Json {
+        return Err(AppError::Protocol(format!("{:?} cannot be sent as lines", options.format.codec)));
+    }
+
+    let mut session = Session::with_codec(options.format.codec);
+    let outbound = Outbound::with_format(socket.clone(), options.format);
+    session.outbound = Some(outbound.clone());
+    let mut from_client = receive_messages(socket, options.format);
+    let serving = async {
+        loop {
+            let next_message = match within(options.idle_timeout, from_client.next()).await {
+                Ok(next_message) => next_message,
+                Err(_) => break,
+            };
+            let message = match next_message {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => match error_reply(&e) {
+                    Some(response) => {
+                        within(options.write_timeout, outbound.send(&response)).await??;
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+                None => break,
+            };
+            for reply in dispatch(message, &mut session, store) {
+                within(options.write_timeout, outbound.send(&reply)).await??;
             }
-            Message::SetDataRequest(r) => {
-                for LabeledValue { label, value } in r.params {
-                    store.insert(label, value);
-                }
-                let response = Message::SetDataResponse(SetDataResponse {
-                    tag: r.tag,
-                    status: Status::OK,
-                });
-                outbound.send(&response).await?;
+            if session.closed {
+                break;
             }
-            _ => (),
         }
+        Ok(())
+    };
+
+    let result = within(options.connection_timeout, serving).await.unwrap_or(Ok(()));
+    if let Some(subscriber) = session.subscriber {
+        store.unsubscribe(subscriber);
     }
+    result
+}
 
-    Ok(())
+/// Awaits `future`, giving up after `limit` when there is one.
+async fn within<F: Future>(limit: Option<Duration>, future: F) -> Result<F::Output, future::TimeoutError> {
+    match limit {
+        Some(limit) => future::timeout(limit, future).await,
+        None => Ok(future.await),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::common::Value;
-    use crate::utils::{self, AppResult};
+    use crate::utils::{self, AppError, AppResult};
     use crate::message::*;
-    use std::collections::HashMap;
+    use crate::store::Store;
+    use crate::alarm::{AlarmLevel, AlarmRule};
+    use crate::permission::WriteAccess;
+    use crate::auth::{self, Credentials, UserCredential};
+    use crate::tls::TlsConfig;
+    use crate::codec::{Codec, Format, DEFAULT_MAX_FRAME_SIZE};
+    use crate::heartbeat;
+    use crate::message_receiver::Outbound;
+    use std::time::Duration;
+    use crate::utils::SharedStream;
+    use futures_rustls::TlsConnector;
+    use futures_rustls::rustls::{ClientConfig, RootCertStore};
+    use futures_rustls::rustls::crypto::ring;
+    use futures_rustls::rustls::pki_types::{PrivateKeyDer, ServerName};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::sync::Arc;
     use async_std::prelude::*;
     use async_std::{net, task};
     use async_std::io::BufReader;
@@ -98,11 +231,12 @@ mod test {
 
         task::block_on(async {
 
-            let mut store = HashMap::new();
+            let mut store = Store::new();
+
+            let listener = net::TcpListener::bind("localhost:8888").await.unwrap();
 
             // server
             let server_fut = async {
-                let listener = net::TcpListener::bind("localhost:8888").await?;
                 let mut new_connections = listener.incoming();
                 while let Some(socket_result) = new_connections.next().await {
                     let socket = socket_result?;
@@ -121,8 +255,8 @@ mod test {
                 let message = Message::SetDataRequest(SetDataRequest {
                     tag: Some("ABC".to_string()),
                     params: vec![
-                        LabeledValue { label: "SP1".to_string(), value: Value::Float(3.0) },
-                        LabeledValue { label: "NE1".to_string(), value: Value::Int(10) },
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                        LabeledValue::new("NE1".to_string(), Value::Int(10)),
                     ],
                 });
                 utils::send_as_json(&mut socket, &message).await?;
@@ -130,15 +264,14 @@ mod test {
                 task::yield_now().await;
 
                 // recv SendDataResponse
-                while let Some(message_result) = from_client.next().await {
+                if let Some(message_result) = from_client.next().await {
                     let message: Message = message_result?;
                     if let Message::SetDataResponse(r) = message {
                         assert_eq!(r.tag, Some("ABC".to_string()));
                         assert_eq!(r.status, Status::OK);
                     } else {
-                        assert!(false);
+                        panic!("not SetDataResponse");
                     }
-                    break;
                 }
 
                 // send GetDataRequest
@@ -151,7 +284,7 @@ mod test {
                 task::yield_now().await;
 
                 // recv GetDataResponse
-                while let Some(message_result) = from_client.next().await {
+                if let Some(message_result) = from_client.next().await {
                     let message: Message = message_result?;
                     if let Message::GetDataResponse(r) = message {
                         assert_eq!(r.tag, Some("123".to_string()));
@@ -161,9 +294,8 @@ mod test {
                         assert_eq!(r.results[1].label, "SP1".to_string());
                         assert_eq!(r.results[1].value, Value::Float(3.0));
                     } else {
-                        assert!(false);
+                        panic!("not GetDataResponse");
                     }
-                    break;
                 }
 
                 Ok(()) as AppResult<()>
@@ -180,12 +312,13 @@ mod test {
 
         task::block_on(async {
 
-            let mut store = HashMap::new();
+            let mut store = Store::new();
+
+            // TODO: I want to change bind port to 8888.
+            let listener = net::TcpListener::bind("localhost:8889").await.unwrap();
 
             // server
             let server_fut = async {
-                // TODO: I want to change bind port to 8888.
-                let listener = net::TcpListener::bind("localhost:8889").await?;
                 let mut new_connections = listener.incoming();
                 while let Some(socket_result) = new_connections.next().await {
                     let socket = socket_result?;
@@ -204,7 +337,7 @@ mod test {
                 let message = Message::SetDataRequest(SetDataRequest {
                     tag: None,
                     params: vec![
-                        LabeledValue { label: "SP1".to_string(), value: Value::Float(3.0) },
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
                     ],
                 });
                 utils::send_as_json(&mut socket, &message).await?;
@@ -212,10 +345,9 @@ mod test {
                 task::yield_now().await;
 
                 // recv SendDataResponse
-                while let Some(message_result) = from_client.next().await {
+                if let Some(message_result) = from_client.next().await {
                     let message: Message = message_result?;
                     assert!(matches!(message, Message::SetDataResponse(..)));
-                    break;
                 }
 
                 // send GetDataRequest
@@ -228,7 +360,7 @@ mod test {
                 task::yield_now().await;
 
                 // recv GetDataResponse
-                while let Some(message_result) = from_client.next().await {
+                if let Some(message_result) = from_client.next().await {
                     let message: Message = message_result?;
                     if let Message::GetDataResponse(r) = message {
                         assert_eq!(r.status, Status::NotFound);
@@ -237,9 +369,504 @@ mod test {
                         assert_eq!(r.results[1].label, "SP1".to_string());
                         assert_eq!(r.results[1].value, Value::Float(3.0));
                     } else {
-                        assert!(false);
+                        panic!("not GetDataResponse");
+                    }
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_alarm_subscribe_and_acknowledge() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule {
+                high: Some(80.0),
+                hysteresis: 1.0,
+                ..Default::default()
+            });
+
+            let listener = net::TcpListener::bind("localhost:8891").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8891").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send SubscribeAlarmRequest
+                let message = Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag: None });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                assert!(matches!(message, Message::SubscribeAlarmResponse(..)));
+
+                // send SetDataRequest over the high limit
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: None,
+                    params: vec![
+                        LabeledValue::new("TI1".to_string(), Value::Float(85.0)),
+                    ],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                assert!(matches!(message, Message::SetDataResponse(..)));
+
+                // recv AlarmNotification
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::AlarmNotification(n) = message {
+                    assert_eq!(n.label, "TI1".to_string());
+                    assert_eq!(n.state, AlarmState::Active);
+                    assert_eq!(n.level, AlarmLevel::High);
+                    assert_eq!(n.value, Value::Float(85.0));
+                } else {
+                    panic!("not AlarmNotification");
+                }
+
+                // send AckAlarmRequest
+                let message = Message::AckAlarmRequest(AckAlarmRequest {
+                    tag: Some("ACK".to_string()),
+                    params: vec!["TI1".to_string()],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::AckAlarmResponse(r) = message {
+                    assert_eq!(r.tag, Some("ACK".to_string()));
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not AckAlarmResponse");
+                }
+
+                // send GetAlarmRequest
+                let message = Message::GetAlarmRequest(GetAlarmRequest { tag: None });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::GetAlarmResponse(r) = message {
+                    assert_eq!(r.results.len(), 1);
+                    assert!(r.results[0].acknowledged);
+                } else {
+                    panic!("not GetAlarmResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_set_request_read_only_label() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.set("PV1".to_string(), Value::Float(1.0));
+            store.permissions.set_access("PV1".to_string(), WriteAccess::ReadOnly);
+
+            let listener = net::TcpListener::bind("localhost:8892").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8892").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: None,
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                        LabeledValue::new("PV1".to_string(), Value::Float(2.0)),
+                    ],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::SetDataResponse(r) = message {
+                    assert_eq!(r.status, Status::ReadOnly);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                // nothing is written by a rejected request
+                let message = Message::GetDataRequest(GetDataRequest {
+                    tag: None,
+                    params: vec!["SP1".to_string(), "PV1".to_string()],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::GetDataResponse(r) = message {
+                    assert_eq!(r.status, Status::NotFound);
+                    assert_eq!(r.results[0].value, Value::Null);
+                    assert_eq!(r.results[1].value, Value::Float(1.0));
+                } else {
+                    panic!("not GetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_login_required() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.permissions.set_access("SP1".to_string(), WriteAccess::Restricted {
+                clients: vec![],
+                roles: vec!["operator".to_string()],
+            });
+            store.credentials = Some(Credentials {
+                users: vec![UserCredential {
+                    name: "hmi1".to_string(),
+                    password_hash: auth::hash_password("secret").unwrap(),
+                    roles: vec!["operator".to_string()],
+                }],
+                tokens: vec![],
+            });
+
+            let listener = net::TcpListener::bind("localhost:8893").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8893").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                let set_request = || Message::SetDataRequest(SetDataRequest {
+                    tag: Some("SET".to_string()),
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                    ],
+                });
+
+                // send SetDataRequest before login
+                utils::send_as_json(&mut socket, &set_request()).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::SetDataResponse(r) = message {
+                    assert_eq!(r.tag, Some("SET".to_string()));
+                    assert_eq!(r.status, Status::Unauthorized);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                // send LoginRequest with a wrong password
+                let message = Message::LoginRequest(LoginRequest {
+                    tag: None,
+                    token: None,
+                    username: Some("hmi1".to_string()),
+                    password: Some("wrong".to_string()),
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::LoginResponse(r) = message {
+                    assert_eq!(r.status, Status::Unauthorized);
+                } else {
+                    panic!("not LoginResponse");
+                }
+
+                // send LoginRequest
+                let message = Message::LoginRequest(LoginRequest {
+                    tag: None,
+                    token: None,
+                    username: Some("hmi1".to_string()),
+                    password: Some("secret".to_string()),
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::LoginResponse(r) = message {
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not LoginResponse");
+                }
+
+                // send SetDataRequest as an operator
+                utils::send_as_json(&mut socket, &set_request()).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::SetDataResponse(r) = message {
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_serve_tls_with_client_certificate() {
+
+        task::block_on(async {
+
+            // self-signed authority that issues both server and client certificates
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(vec![]).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            let server_key = KeyPair::generate().unwrap();
+            let server_cert = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+                .signed_by(&server_key, &ca, &ca_key).unwrap();
+            let client_key = KeyPair::generate().unwrap();
+            let client_cert = CertificateParams::new(vec!["hmi1".to_string()]).unwrap()
+                .signed_by(&client_key, &ca, &ca_key).unwrap();
+
+            let dir = std::env::temp_dir();
+            let (cert_path, key_path, ca_path) = (
+                dir.join("datamanager_test_server.crt"),
+                dir.join("datamanager_test_server.key"),
+                dir.join("datamanager_test_ca.crt"),
+            );
+            std::fs::write(&cert_path, server_cert.pem()).unwrap();
+            std::fs::write(&key_path, server_key.serialize_pem()).unwrap();
+            std::fs::write(&ca_path, ca.pem()).unwrap();
+
+            let acceptor = TlsConfig::new(cert_path, key_path).client_ca(ca_path).acceptor().unwrap();
+            let mut store = Store::new();
+            let listener = net::TcpListener::bind("localhost:8894").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve_tls(socket, &acceptor, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                let mut roots = RootCertStore::empty();
+                roots.add(ca.der().clone())?;
+                let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                    .with_safe_default_protocol_versions()?
+                    .with_root_certificates(roots)
+                    .with_client_auth_cert(
+                        vec![client_cert.der().clone()],
+                        PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+                    )?;
+                let connector = TlsConnector::from(Arc::new(config));
+
+                // connect
+                let socket = net::TcpStream::connect("localhost:8894").await?;
+                let stream = connector.connect(ServerName::try_from("localhost").unwrap(), socket).await?;
+                let mut socket = SharedStream::new(stream);
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: Some("TLS".to_string()),
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                    ],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                socket.flush().await?;
+
+                // recv SetDataResponse
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::SetDataResponse(r) = message {
+                    assert_eq!(r.tag, Some("TLS".to_string()));
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_unix() {
+        use async_std::os::unix::net::UnixStream;
+
+        task::block_on(async {
+
+            let path = std::env::temp_dir().join("datamanager_test.sock");
+            let mut store = Store::new();
+
+            // server
+            let server_fut = super::serve_unix(&path, &mut store);
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = loop {
+                    match UnixStream::connect(&path).await {
+                        Ok(socket) => break socket,
+                        Err(_) => task::yield_now().await,
+                    }
+                };
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: None,
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                    ],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                assert!(matches!(message, Message::SetDataResponse(..)));
+
+                // send GetDataRequest
+                let message = Message::GetDataRequest(GetDataRequest {
+                    tag: None,
+                    params: vec!["SP1".to_string()],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::GetDataResponse(r) = message {
+                    assert_eq!(r.status, Status::OK);
+                    assert_eq!(r.results[0].value, Value::Float(3.0));
+                } else {
+                    panic!("not GetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_unix_socket_file() {
+        use async_std::os::unix::net::UnixStream;
+        use std::os::unix::fs::PermissionsExt;
+
+        task::block_on(async {
+
+            let path = std::env::temp_dir().join("datamanager_test_mode.sock");
+            let mut store = Store::new();
+
+            // a file that is not a socket is left alone
+            let _ = std::fs::remove_file(&path);
+            std::fs::write(&path, "data").unwrap();
+            let result = super::serve_unix(&path, &mut store).await;
+            assert!(matches!(result, Err(AppError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists));
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+            std::fs::remove_file(&path).unwrap();
+
+            // server
+            let server_fut = super::serve_unix_with_mode(&path, 0o600, &mut store);
+
+            // client
+            let client_fut = async {
+                loop {
+                    match UnixStream::connect(&path).await {
+                        Ok(..) => break,
+                        Err(_) => task::yield_now().await,
                     }
-                    break;
+                }
+                let mode = std::fs::metadata(&path)?.permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_serve_with_message_pack() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = super::ServeOptions {
+                format: Format::new(Codec::MessagePack),
+                ..Default::default()
+            };
+            let listener = net::TcpListener::bind("localhost:8898").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8898").await?;
+                let mut from_client = utils::receive_frames(socket.clone(), DEFAULT_MAX_FRAME_SIZE);
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: Some("MP".to_string()),
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                    ],
+                });
+                utils::send_as_frame(&mut socket, &Codec::MessagePack.encode(&message)?).await?;
+
+                // recv SetDataResponse
+                let frame = from_client.next().await.unwrap()?;
+                if let Message::SetDataResponse(r) = Codec::MessagePack.decode(&frame)? {
+                    assert_eq!(r.tag, Some("MP".to_string()));
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not SetDataResponse");
                 }
 
                 Ok(()) as AppResult<()>
@@ -250,6 +877,252 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_idle_timeout_with_keepalive() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = super::ServeOptions {
+                idle_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let listener = net::TcpListener::bind("localhost:8899").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                if let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let socket = net::TcpStream::connect("localhost:8899").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // keep the connection alive longer than the idle timeout
+                let pongs = async {
+                    let mut pongs = 0;
+                    while let Some(message_result) = from_client.next().await {
+                        let message: Message = message_result?;
+                        assert!(matches!(message, Message::Pong(..)));
+                        pongs += 1;
+                    }
+                    Ok(pongs) as AppResult<i32>
+                };
+                let keepalive = async {
+                    heartbeat::keepalive(&Outbound::new(socket.clone()), Duration::from_millis(30))
+                        .timeout(Duration::from_millis(300)).await.ok();
+                    // the server closes the connection once the pings stop
+                    task::sleep(Duration::from_secs(5)).await;
+                    Err(AppError::Protocol("connection was not closed".to_string())) as AppResult<i32>
+                };
+                let pongs = pongs.race(keepalive).await?;
+                assert!(pongs >= 5);
+
+                Ok(()) as AppResult<()>
+            };
+
+            let (server_result, client_result) = server_fut.join(client_fut).await;
+            assert!(matches!(server_result, Ok(..)));
+            assert!(matches!(client_result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_connection_timeout() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = super::ServeOptions {
+                idle_timeout: Some(Duration::from_secs(5)),
+                connection_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let listener = net::TcpListener::bind("localhost:8900").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                if let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let socket = net::TcpStream::connect("localhost:8900").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // an active client is still closed after the connection timeout
+                let pongs = async {
+                    while let Some(message_result) = from_client.next().await {
+                        match message_result {
+                            Ok(message) => { let _message: Message = message; }
+                            // pings the server left unread reset the connection
+                            Err(AppError::Io(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => break,
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    Ok(()) as AppResult<()>
+                };
+                let keepalive = async {
+                    heartbeat::keepalive(&Outbound::new(socket.clone()), Duration::from_millis(20)).await.ok();
+                    task::sleep(Duration::from_secs(5)).await;
+                    Err(AppError::Protocol("connection was not closed".to_string())) as AppResult<()>
+                };
+                pongs.race(keepalive).timeout(Duration::from_secs(2)).await??;
+
+                Ok(()) as AppResult<()>
+            };
+
+            let (server_result, client_result) = server_fut.join(client_fut).await;
+            assert!(matches!(server_result, Ok(..)));
+            assert!(matches!(client_result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_oversized_line_is_rejected() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let options = super::ServeOptions {
+                format: Format::default().max_frame_size(64),
+                ..Default::default()
+            };
+            let listener = net::TcpListener::bind("localhost:8901").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve_with(socket, &mut store, &options).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8901").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send a SetDataRequest over the limit
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: None,
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::String("X".repeat(1000))),
+                    ],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::ErrorResponse(r) = message {
+                    assert_eq!(r.status, Status::InvalidRequest);
+                } else {
+                    panic!("not ErrorResponse");
+                }
+
+                // the connection is still usable
+                let message = Message::GetDataRequest(GetDataRequest {
+                    tag: None,
+                    params: vec!["SP1".to_string()],
+                });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::GetDataResponse(r) = message {
+                    assert_eq!(r.status, Status::NotFound);
+                } else {
+                    panic!("not GetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_malformed_input_is_answered() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let listener = net::TcpListener::bind("localhost:8902").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    super::serve(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let mut socket = net::TcpStream::connect("localhost:8902").await?;
+                let mut from_client = utils::receive_as_json(BufReader::new(socket.clone()));
+
+                // send an unknown command and broken JSON
+                socket.write_all(b"{\"command\":\"Reboot\",\"tag\":\"R1\"}\n{\"command\n").await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::ErrorResponse(r) = message {
+                    assert_eq!(r.tag, Some("R1".to_string()));
+                    assert_eq!(r.status, Status::InvalidRequest);
+                } else {
+                    panic!("not ErrorResponse");
+                }
+                let message: Message = from_client.next().await.unwrap()?;
+                if let Message::ErrorResponse(r) = message {
+                    assert_eq!(r.tag, None);
+                } else {
+                    panic!("not ErrorResponse");
+                }
+
+                // the connection is still usable
+                let message = Message::Ping(Ping { tag: None });
+                utils::send_as_json(&mut socket, &message).await?;
+                let message: Message = from_client.next().await.unwrap()?;
+                assert!(matches!(message, Message::Pong(..)));
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_serve_duplex_until_eof() {
+        let input = format!("{}\n{}\n",
+            r#"{"command":"SetDataRequest","params":[{"label":"SP1","value":3.0}]}"#,
+            r#"{"command":"Ping"}"#);
+        let stdio = utils::Duplex::new(utils::test::StdinLike::new(&input), async_std::io::sink());
+        let mut store = Store::new();
+
+        // stdin reaching EOF ends serving cleanly
+        let result = task::block_on(super::serve(SharedStream::new(stdio), &mut store));
+        assert!(matches!(result, Ok(..)));
+        assert_eq!(store.get("SP1"), Some(&Value::Float(3.0)));
+    }
+
     #[test]
     fn test_connection() {
         task::block_on(async {
@@ -260,7 +1133,7 @@ mod test {
                 let message = Message::SetDataRequest(SetDataRequest {
                     tag: None,
                     params: vec![
-                        LabeledValue { label: "SP1".to_string(), value: Value::Float(3.0) },
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
                     ],
                 });
                 utils::send_as_json(&mut socket, &message).await?;
@@ -289,7 +1162,7 @@ mod test {
 
     impl Stream for SampleStream {
         type Item = i32;
-        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
             if self.now == self.max {
                 Poll::Ready(None)
             } else {
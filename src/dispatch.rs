@@ -0,0 +1,390 @@
+use crate::common::Value;
+use crate::utils::AppError;
+use crate::codec::Codec;
+use crate::message::*;
+use crate::message_receiver::Outbound;
+use crate::permission::ClientIdentity;
+use crate::store::Store;
+
+/// Optional protocol features the server can agree to in `HelloResponse`.
+pub const SUPPORTED_FEATURES: &[&str] = &["alarms", "login"];
+
+/// State of one client connection that is kept between requests.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Codec the connection is served with.
+    pub codec: Codec,
+    pub client_name: Option<String>,
+    pub features: Vec<String>,
+    pub client: Option<ClientIdentity>,
+    pub alarm_subscribed: bool,
+    /// Where alarm notifications caused by other clients are queued. Without
+    /// it, a subscriber only sees the notifications of its own requests.
+    pub outbound: Option<Outbound>,
+    /// Id of the registration in `Store`, to unsubscribe with when the
+    /// connection ends.
+    pub subscriber: Option<u64>,
+    /// Set when the connection should be closed after the replies are sent.
+    pub closed: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_codec(codec: Codec) -> Self {
+        Self { codec, ..Self::default() }
+    }
+}
+
+/// Handles one message from a client and returns what to send back: the
+/// response first, followed by any notifications the request caused.
+pub fn dispatch(message: Message, session: &mut Session, store: &mut Store) -> Vec<Message> {
+    let logged_in = store.credentials.is_none() || session.client.is_some();
+    let before_login = matches!(message, Message::LoginRequest(..) | Message::HelloRequest(..) | Message::Ping(..));
+    if !logged_in && !before_login {
+        let tag = message.tag().cloned();
+        let response = error_response(message, Status::Unauthorized).unwrap_or_else(|| {
+            Message::ErrorResponse(ErrorResponse {
+                tag,
+                status: Status::Unauthorized,
+                reason: "login required".to_string(),
+            })
+        });
+        return vec![response];
+    }
+
+    match message {
+        Message::Ping(r) => vec![Message::Pong(Pong { tag: r.tag })],
+        Message::HelloRequest(r) => {
+            // newer clients are answered with the version the server speaks
+            let rejection = if r.version < MIN_PROTOCOL_VERSION {
+                Some(format!("protocol version {} is older than {}, the oldest supported",
+                    r.version, MIN_PROTOCOL_VERSION))
+            } else if !r.codecs.is_empty() && !r.codecs.contains(&session.codec) {
+                Some(format!("the connection is served with {:?}", session.codec))
+            } else {
+                None
+            };
+            let (status, version) = match rejection {
+                None => {
+                    session.client_name = Some(r.client_name);
+                    session.features = r.features.into_iter()
+                        .filter(|feature| SUPPORTED_FEATURES.contains(&feature.as_str()))
+                        .collect();
+                    (Status::OK, r.version.min(PROTOCOL_VERSION))
+                }
+                Some(..) => {
+                    session.closed = true;
+                    (Status::InvalidRequest, PROTOCOL_VERSION)
+                }
+            };
+            vec![Message::HelloResponse(HelloResponse {
+                tag: r.tag,
+                status,
+                version,
+                codec: session.codec,
+                features: session.features.clone(),
+                reason: rejection,
+            })]
+        }
+        Message::LoginRequest(r) => {
+            let status = match &store.credentials {
+                None => Status::OK,
+                Some(credentials) => {
+                    session.client = credentials.authenticate(&r);
+                    if session.client.is_some() { Status::OK } else { Status::Unauthorized }
+                }
+            };
+            vec![Message::LoginResponse(LoginResponse {
+                tag: r.tag,
+                status,
+            })]
+        }
+        Message::GetDataRequest(r) => {
+            let results: Vec<LabeledValue> = r.params.into_iter().map(|label| match store.get(&label) {
+                Some(value) => LabeledValue::result(label, value.clone(), Status::OK, None),
+                None => LabeledValue::result(label, Value::Null, Status::NotFound,
+                    Some("no such label".to_string())),
+            }).collect();
+            let status =
+                if results.iter().all(|result| result.status == Some(Status::OK)) { Status::OK }
+                else { Status::NotFound };
+            vec![Message::GetDataResponse(GetDataResponse {
+                tag: r.tag,
+                status,
+                results,
+            })]
+        }
+        Message::SetDataRequest(r) => {
+            let mut notifications = Vec::new();
+            let access: Vec<Status> = r.params.iter()
+                .map(|p| store.permissions.write_status(&p.label, session.client.as_ref()))
+                .collect();
+            // the batch is applied as a whole or not at all
+            let status = access.iter().copied().find(|access| *access != Status::OK).unwrap_or(Status::OK);
+            let results = r.params.into_iter().zip(access).map(|(LabeledValue { label, value, .. }, access)| {
+                if access == Status::ReadOnly {
+                    LabeledValue::result(label, value, access, Some("label is read-only".to_string()))
+                } else if access != Status::OK {
+                    LabeledValue::result(label, value, access, Some("write access denied".to_string()))
+                } else if status != Status::OK {
+                    LabeledValue::result(label, value, Status::InvalidRequest,
+                        Some("not written because another label was rejected".to_string()))
+                } else {
+                    if let Some(notification) = store.set(label.clone(), value.clone()) {
+                        notifications.push(notification);
+                    }
+                    LabeledValue::result(label, value, Status::OK, None)
+                }
+            }).collect();
+            let mut replies = vec![Message::SetDataResponse(SetDataResponse {
+                tag: r.tag,
+                status,
+                results,
+            })];
+            for notification in notifications.iter() {
+                store.broadcast(notification, session.subscriber);
+            }
+            if session.alarm_subscribed {
+                replies.extend(notifications.into_iter().map(Message::AlarmNotification));
+            }
+            replies
+        }
+        Message::SubscribeAlarmRequest(r) => {
+            session.alarm_subscribed = true;
+            if let (Some(outbound), None) = (&session.outbound, session.subscriber) {
+                session.subscriber = Some(store.subscribe(outbound.clone()));
+            }
+            vec![Message::SubscribeAlarmResponse(SubscribeAlarmResponse {
+                tag: r.tag,
+                status: Status::OK,
+            })]
+        }
+        Message::GetAlarmRequest(r) => {
+            vec![Message::GetAlarmResponse(GetAlarmResponse {
+                tag: r.tag,
+                status: Status::OK,
+                results: store.alarms.active_alarms(),
+            })]
+        }
+        Message::AckAlarmRequest(r) => {
+            let mut status = Status::OK;
+            for label in r.params.iter() {
+                if !store.alarms.acknowledge(label) {
+                    status = Status::NotFound;
+                }
+            }
+            vec![Message::AckAlarmResponse(AckAlarmResponse {
+                tag: r.tag,
+                status,
+            })]
+        }
+        other => vec![Message::ErrorResponse(ErrorResponse {
+            tag: other.tag().cloned(),
+            status: Status::InvalidRequest,
+            reason: format!("unsupported command: {}", other.command()),
+        })],
+    }
+}
+
+/// Answers input that could not be read as a request. Returns None for
+/// errors after which the connection cannot continue.
+pub fn error_reply(error: &AppError) -> Option<Message> {
+    let tag = match error {
+        AppError::Decode(malformed) => malformed.tag.clone(),
+        AppError::FrameTooLarge(..) => None,
+        _ => return None,
+    };
+    Some(Message::ErrorResponse(ErrorResponse {
+        tag,
+        status: error.into(),
+        reason: error.to_string(),
+    }))
+}
+
+/// Builds the response that matches `request`, carrying only `status`.
+pub fn error_response(request: Message, status: Status) -> Option<Message> {
+    let response = match request {
+        Message::GetDataRequest(r) => Message::GetDataResponse(GetDataResponse {
+            tag: r.tag,
+            status,
+            results: vec![],
+        }),
+        Message::SetDataRequest(r) => Message::SetDataResponse(SetDataResponse {
+            tag: r.tag,
+            status,
+            results: vec![],
+        }),
+        Message::SubscribeAlarmRequest(r) => Message::SubscribeAlarmResponse(SubscribeAlarmResponse {
+            tag: r.tag,
+            status,
+        }),
+        Message::GetAlarmRequest(r) => Message::GetAlarmResponse(GetAlarmResponse {
+            tag: r.tag,
+            status,
+            results: vec![],
+        }),
+        Message::AckAlarmRequest(r) => Message::AckAlarmResponse(AckAlarmResponse {
+            tag: r.tag,
+            status,
+        }),
+        Message::LoginRequest(r) => Message::LoginResponse(LoginResponse {
+            tag: r.tag,
+            status,
+        }),
+        _ => return None,
+    };
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::WriteAccess;
+
+    fn hello(version: u32, codecs: Vec<Codec>) -> Message {
+        Message::HelloRequest(HelloRequest {
+            tag: Some("HI".to_string()),
+            version,
+            client_name: "hmi1".to_string(),
+            codecs,
+            features: vec!["alarms".to_string(), "compression".to_string()],
+        })
+    }
+
+    #[test]
+    fn test_hello_accepted() {
+        let mut store = Store::new();
+        let mut session = Session::with_codec(Codec::MessagePack);
+
+        let replies = dispatch(hello(PROTOCOL_VERSION + 1, vec![Codec::Cbor, Codec::MessagePack]), &mut session, &mut store);
+
+        assert_eq!(replies, vec![Message::HelloResponse(HelloResponse {
+            tag: Some("HI".to_string()),
+            status: Status::OK,
+            version: PROTOCOL_VERSION,
+            codec: Codec::MessagePack,
+            features: vec!["alarms".to_string()],
+            reason: None,
+        })]);
+        assert_eq!(session.client_name, Some("hmi1".to_string()));
+        assert!(!session.closed);
+    }
+
+    #[test]
+    fn test_hello_rejected() {
+        let mut store = Store::new();
+
+        let mut session = Session::new();
+        let replies = dispatch(hello(MIN_PROTOCOL_VERSION - 1, vec![]), &mut session, &mut store);
+        if let Message::HelloResponse(r) = &replies[0] {
+            assert_eq!(r.status, Status::InvalidRequest);
+            assert_eq!(r.version, PROTOCOL_VERSION);
+            assert!(r.reason.is_some());
+        } else {
+            panic!("not HelloResponse");
+        }
+        assert!(session.closed);
+
+        let mut session = Session::new();
+        let replies = dispatch(hello(PROTOCOL_VERSION, vec![Codec::Cbor]), &mut session, &mut store);
+        assert!(matches!(&replies[0], Message::HelloResponse(r) if r.status == Status::InvalidRequest));
+        assert!(session.closed);
+    }
+
+    #[test]
+    fn test_unsupported_command() {
+        let mut store = Store::new();
+        let mut session = Session::new();
+
+        let message = Message::SetDataResponse(SetDataResponse {
+            tag: Some("ABC".to_string()),
+            status: Status::OK,
+            results: vec![],
+        });
+        let replies = dispatch(message, &mut session, &mut store);
+
+        assert_eq!(replies, vec![Message::ErrorResponse(ErrorResponse {
+            tag: Some("ABC".to_string()),
+            status: Status::InvalidRequest,
+            reason: "unsupported command: SetDataResponse".to_string(),
+        })]);
+    }
+
+    #[test]
+    fn test_per_label_results() {
+        let mut store = Store::new();
+        let mut session = Session::new();
+        store.set("SP1".to_string(), Value::Null);
+        store.permissions.set_access("PV1".to_string(), WriteAccess::ReadOnly);
+
+        let message = Message::GetDataRequest(GetDataRequest {
+            tag: None,
+            params: vec!["SP1".to_string(), "NE1".to_string()],
+        });
+        if let Message::GetDataResponse(r) = &dispatch(message, &mut session, &mut store)[0] {
+            assert_eq!(r.status, Status::NotFound);
+            assert_eq!(r.results[0], LabeledValue::result("SP1".to_string(), Value::Null, Status::OK, None));
+            assert_eq!(r.results[1].value, Value::Null);
+            assert_eq!(r.results[1].status, Some(Status::NotFound));
+            assert!(r.results[1].error.is_some());
+        } else {
+            panic!("not GetDataResponse");
+        }
+
+        let message = Message::SetDataRequest(SetDataRequest {
+            tag: None,
+            params: vec![
+                LabeledValue::new("SP1".to_string(), Value::Int(1)),
+                LabeledValue::new("PV1".to_string(), Value::Int(2)),
+            ],
+        });
+        if let Message::SetDataResponse(r) = &dispatch(message, &mut session, &mut store)[0] {
+            assert_eq!(r.status, Status::ReadOnly);
+            assert_eq!(r.results[0].status, Some(Status::InvalidRequest));
+            assert_eq!(r.results[1].status, Some(Status::ReadOnly));
+        } else {
+            panic!("not SetDataResponse");
+        }
+        assert_eq!(store.get("SP1"), Some(&Value::Null));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_alarm_broadcast_to_other_connections() {
+        use crate::alarm::AlarmRule;
+        use crate::codec::Format;
+        use crate::message_receiver::receive_messages;
+        use async_std::os::unix::net::UnixStream;
+        use async_std::prelude::*;
+        use async_std::task;
+
+        task::block_on(async {
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule { high: Some(80.0), ..Default::default() });
+
+            let (server_a, client_a) = UnixStream::pair().unwrap();
+            let mut subscriber = Session::new();
+            subscriber.outbound = Some(Outbound::new(server_a));
+            let (server_b, _client_b) = UnixStream::pair().unwrap();
+            let mut writer = Session::new();
+            writer.outbound = Some(Outbound::new(server_b));
+
+            dispatch(Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag: None }), &mut subscriber, &mut store);
+            let message = Message::SetDataRequest(SetDataRequest {
+                tag: None,
+                params: vec![LabeledValue::new("TI1".to_string(), Value::Float(85.0))],
+            });
+            let replies = dispatch(message, &mut writer, &mut store);
+            assert!(matches!(replies[..], [Message::SetDataResponse(..)]));
+
+            let mut from_server = receive_messages(client_a, Format::default());
+            match from_server.next().await {
+                Some(Ok(Message::AlarmNotification(notification))) => assert_eq!(notification.label, "TI1"),
+                other => panic!("not AlarmNotification: {:?}", other),
+            }
+        });
+    }
+}
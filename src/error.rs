@@ -0,0 +1,158 @@
+use crate::message::Status;
+use crate::utils::{FrameTooLarge, MalformedMessage};
+use std::fmt;
+use std::io;
+
+/// Errors of this crate. All variants are `Send + Sync`, so they can be
+/// returned from spawned tasks.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to a peer failed.
+    Io(io::Error),
+    /// Input could not be decoded as a message. The stream is still usable.
+    Decode(MalformedMessage),
+    /// A frame or line was over the limit and has been skipped.
+    FrameTooLarge(FrameTooLarge),
+    /// A message could not be encoded.
+    Encode(String),
+    /// The peer or the caller broke the protocol.
+    Protocol(String),
+    /// The store refused or failed an operation.
+    Store(String),
+    /// The server answered a request with a status other than OK.
+    Rejected { status: Status, reason: Option<String> },
+    /// TLS or credential files could not be used.
+    Config(String),
+    /// An operation did not finish in time.
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Decode(e) => write!(f, "{}", e),
+            Error::FrameTooLarge(e) => write!(f, "{}", e),
+            Error::Encode(reason) => write!(f, "cannot encode message: {}", reason),
+            Error::Protocol(reason) => write!(f, "protocol error: {}", reason),
+            Error::Store(reason) => write!(f, "store error: {}", reason),
+            Error::Rejected { status, reason: Some(reason) } => write!(f, "request rejected with {:?}: {}", status, reason),
+            Error::Rejected { status, reason: None } => write!(f, "request rejected with {:?}", status),
+            Error::Config(reason) => write!(f, "invalid configuration: {}", reason),
+            Error::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::FrameTooLarge(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Status reported to a client whose request failed with this error.
+impl From<&Error> for Status {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::Decode(..) | Error::FrameTooLarge(..) | Error::Protocol(..) => Status::InvalidRequest,
+            Error::Rejected { status, .. } => *status,
+            Error::Timeout => Status::Busy,
+            Error::Io(..) | Error::Encode(..) | Error::Store(..) | Error::Config(..) => Status::InternalError,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<MalformedMessage> for Error {
+    fn from(e: MalformedMessage) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<FrameTooLarge> for Error {
+    fn from(e: FrameTooLarge) -> Self {
+        Error::FrameTooLarge(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            Error::Io(e.into())
+        } else {
+            Error::Decode(MalformedMessage { tag: None, reason: e.to_string() })
+        }
+    }
+}
+
+impl From<async_std::future::TimeoutError> for Error {
+    fn from(_: async_std::future::TimeoutError) -> Self {
+        Error::Timeout
+    }
+}
+
+impl From<async_tungstenite::tungstenite::Error> for Error {
+    fn from(e: async_tungstenite::tungstenite::Error) -> Self {
+        match e {
+            async_tungstenite::tungstenite::Error::Io(e) => Error::Io(e),
+            e => Error::Protocol(e.to_string()),
+        }
+    }
+}
+
+impl From<http_types::Error> for Error {
+    fn from(e: http_types::Error) -> Self {
+        match e.downcast::<io::Error>() {
+            Ok(e) => Error::Io(e),
+            Err(e) => Error::Protocol(e.to_string()),
+        }
+    }
+}
+
+impl From<futures_rustls::rustls::Error> for Error {
+    fn from(e: futures_rustls::rustls::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+impl From<futures_rustls::rustls::pki_types::pem::Error> for Error {
+    fn from(e: futures_rustls::rustls::pki_types::pem::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+impl From<futures_rustls::rustls::server::VerifierBuilderError> for Error {
+    fn from(e: futures_rustls::rustls::server::VerifierBuilderError) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn test_error_status() {
+        let error: Error = MalformedMessage { tag: None, reason: "EOF".to_string() }.into();
+        assert_eq!(Status::from(&error), Status::InvalidRequest);
+        let error: Error = io::Error::from(io::ErrorKind::BrokenPipe).into();
+        assert_eq!(Status::from(&error), Status::InternalError);
+        assert_eq!(Status::from(&Error::Timeout), Status::Busy);
+    }
+}
@@ -0,0 +1,16 @@
+use crate::utils::AppResult;
+use crate::message::{Message, Ping};
+use crate::message_receiver::Outbound;
+use async_std::task;
+use std::time::Duration;
+
+/// Sends a `Ping` every `interval` until writing fails. Clients run it next
+/// to their own work so that the server's idle timeout only closes
+/// connections whose peer is gone. The pings go through `to_server`, so they
+/// use its format and are never written in the middle of another message.
+pub async fn keepalive(to_server: &Outbound, interval: Duration) -> AppResult<()> {
+    loop {
+        task::sleep(interval).await;
+        to_server.send(&Message::Ping(Ping { tag: None })).await?;
+    }
+}
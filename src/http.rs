@@ -0,0 +1,239 @@
+use crate::utils::{AppError, AppResult};
+use crate::common::{Label, Value};
+use crate::codec::DEFAULT_MAX_FRAME_SIZE;
+use crate::message::*;
+use crate::dispatch::{dispatch, Session};
+use crate::store::Store;
+use http_types::{Body, Method, Request, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use async_std::prelude::*;
+use std::cell::RefCell;
+
+/// Serves HTTP/1.1 requests on one connection.
+///
+/// * `GET /labels/{label}` reads one label. The label is percent-decoded.
+/// * `PUT /labels/{label}` writes the JSON value in the body.
+/// * `POST /get` reads the JSON array of labels in the body.
+///
+/// Bodies of the replies are the matching protocol responses, and their
+/// `Status` decides the HTTP status code. Bodies over
+/// `DEFAULT_MAX_FRAME_SIZE` are answered with 413. A bearer token in the
+/// `Authorization` header logs the request in.
+pub async fn serve_http<T>(socket: T, store: &mut Store) -> AppResult<()>
+where
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin
+        + std::clone::Clone + Send + Sync + 'static,
+{
+    let store = RefCell::new(store);
+    async_h1::accept(socket, |request| handle(request, &store))
+        .await
+        .map_err(AppError::from)
+}
+
+async fn handle(mut request: Request, store: &RefCell<&mut Store>) -> http_types::Result<Response> {
+    let path = request.url().path().to_string();
+    let token = request.header("Authorization")
+        .and_then(|value| value.as_str().strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let label = match path.strip_prefix("/labels/").map(|label| percent_decode_str(label).decode_utf8()) {
+        Some(Ok(label)) if !label.is_empty() => Some(label.into_owned()),
+        Some(_) => return Ok(Response::new(StatusCode::BadRequest)),
+        None => None,
+    };
+
+    let message = match (request.method(), label, path.as_str()) {
+        (Method::Get, Some(label), _) => Message::GetDataRequest(GetDataRequest {
+            tag: None,
+            params: vec![label],
+        }),
+        (Method::Put, Some(label), _) => match read_json::<Value>(&mut request).await {
+            Ok(value) => Message::SetDataRequest(SetDataRequest {
+                tag: None,
+                params: vec![LabeledValue::new(label, value)],
+            }),
+            Err(StatusCode::PayloadTooLarge) => return Ok(Response::new(StatusCode::PayloadTooLarge)),
+            Err(_) => return reply(Message::SetDataResponse(SetDataResponse {
+                tag: None,
+                status: Status::InvalidRequest,
+                results: vec![],
+            })),
+        },
+        (Method::Post, _, "/get") => match read_json::<Vec<Label>>(&mut request).await {
+            Ok(params) => Message::GetDataRequest(GetDataRequest { tag: None, params }),
+            Err(StatusCode::PayloadTooLarge) => return Ok(Response::new(StatusCode::PayloadTooLarge)),
+            Err(_) => return reply(Message::GetDataResponse(GetDataResponse {
+                tag: None,
+                status: Status::InvalidRequest,
+                results: vec![],
+            })),
+        },
+        (_, Some(_), _) | (_, _, "/get") => return Ok(Response::new(StatusCode::MethodNotAllowed)),
+        _ => return Ok(Response::new(StatusCode::NotFound)),
+    };
+
+    let mut session = Session::new();
+    let mut store = store.borrow_mut();
+    if token.is_some() {
+        let login = Message::LoginRequest(LoginRequest {
+            tag: None,
+            token,
+            username: None,
+            password: None,
+        });
+        dispatch(login, &mut session, &mut store);
+    }
+    match dispatch(message, &mut session, &mut store).into_iter().next() {
+        Some(response) => reply(response),
+        None => Ok(Response::new(StatusCode::InternalServerError)),
+    }
+}
+
+/// Reads the body as JSON without buffering more than
+/// `DEFAULT_MAX_FRAME_SIZE` bytes of it.
+async fn read_json<T: DeserializeOwned>(request: &mut Request) -> Result<T, StatusCode> {
+    if request.len().is_some_and(|len| len > DEFAULT_MAX_FRAME_SIZE) {
+        return Err(StatusCode::PayloadTooLarge);
+    }
+    let mut body = Vec::new();
+    request.take_body().take(DEFAULT_MAX_FRAME_SIZE as u64 + 1).read_to_end(&mut body).await
+        .map_err(|_| StatusCode::BadRequest)?;
+    if body.len() > DEFAULT_MAX_FRAME_SIZE {
+        return Err(StatusCode::PayloadTooLarge);
+    }
+    serde_json::from_slice(&body).map_err(|_| StatusCode::BadRequest)
+}
+
+fn reply(response: Message) -> http_types::Result<Response> {
+    let (status, body) = match &response {
+        Message::GetDataResponse(r) => (&r.status, Body::from_json(r)?),
+        Message::SetDataResponse(r) => (&r.status, Body::from_json(r)?),
+        _ => return Ok(Response::new(StatusCode::InternalServerError)),
+    };
+    let mut http_response = Response::new(status_code(status));
+    http_response.set_body(body);
+    Ok(http_response)
+}
+
+fn status_code(status: &Status) -> StatusCode {
+    match status {
+        Status::OK => StatusCode::Ok,
+        Status::InvalidRequest => StatusCode::BadRequest,
+        Status::NotFound => StatusCode::NotFound,
+        Status::Forbidden => StatusCode::Forbidden,
+        Status::Unauthorized => StatusCode::Unauthorized,
+        Status::Conflict => StatusCode::Conflict,
+        Status::TypeMismatch | Status::OutOfRange => StatusCode::UnprocessableEntity,
+        Status::ReadOnly => StatusCode::MethodNotAllowed,
+        Status::Busy => StatusCode::ServiceUnavailable,
+        Status::RateLimited => StatusCode::TooManyRequests,
+        Status::InternalError | Status::Unknown => StatusCode::InternalServerError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::WriteAccess;
+    use async_std::{net, task};
+    use http_types::Url;
+
+    async fn send(mut request: Request) -> AppResult<(StatusCode, String)> {
+        let socket = net::TcpStream::connect("localhost:8896").await?;
+        request.insert_header("Connection", "close");
+        let mut response = async_h1::connect(socket, request)
+            .await?;
+        let body = response.body_string()
+            .await?;
+        Ok((response.status(), body))
+    }
+
+    fn url(path: &str) -> Url {
+        Url::parse("http://localhost:8896").unwrap().join(path).unwrap()
+    }
+
+    #[test]
+    fn test_serve_http() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.permissions.set_access("PV1".to_string(), WriteAccess::ReadOnly);
+            let listener = net::TcpListener::bind("localhost:8896").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    serve_http(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // PUT /labels/SP1
+                let mut request = Request::new(Method::Put, url("/labels/SP1"));
+                request.set_body("3.5");
+                let (status, body) = send(request).await?;
+                assert_eq!(status, StatusCode::Ok);
+                assert_eq!(body, r#"{"status":"OK","results":[{"label":"SP1","value":3.5,"status":"OK"}]}"#);
+
+                // PUT /labels/PV1
+                let mut request = Request::new(Method::Put, url("/labels/PV1"));
+                request.set_body("1");
+                let (status, _) = send(request).await?;
+                assert_eq!(status, StatusCode::MethodNotAllowed);
+
+                // GET /labels/SP1
+                let (status, body) = send(Request::new(Method::Get, url("/labels/SP1"))).await?;
+                assert_eq!(status, StatusCode::Ok);
+                assert_eq!(body, r#"{"status":"OK","results":[{"label":"SP1","value":3.5,"status":"OK"}]}"#);
+
+                // POST /get
+                let mut request = Request::new(Method::Post, url("/get"));
+                request.set_body(r#"["SP1", "NE1"]"#);
+                let (status, body) = send(request).await?;
+                assert_eq!(status, StatusCode::NotFound);
+                assert_eq!(body,
+                    concat!(r#"{"status":"NotFound","results":[{"label":"SP1","value":3.5,"status":"OK"},"#,
+                        r#"{"label":"NE1","value":null,"status":"NotFound","error":"no such label"}]}"#));
+
+                // labels are percent-decoded
+                let mut request = Request::new(Method::Put, url("/labels/TI%201"));
+                request.set_body("20");
+                let (status, body) = send(request).await?;
+                assert_eq!(status, StatusCode::Ok);
+                assert_eq!(body, r#"{"status":"OK","results":[{"label":"TI 1","value":20,"status":"OK"}]}"#);
+
+                // empty or undecodable label
+                let (status, _) = send(Request::new(Method::Get, url("/labels/"))).await?;
+                assert_eq!(status, StatusCode::BadRequest);
+                let (status, _) = send(Request::new(Method::Get, url("/labels/%FF"))).await?;
+                assert_eq!(status, StatusCode::BadRequest);
+
+                // bodies over the limit, with or without a length
+                let body = format!("[{}]", "1,".repeat(DEFAULT_MAX_FRAME_SIZE / 2) + "1");
+                let mut request = Request::new(Method::Put, url("/labels/SP1"));
+                request.set_body(body.as_str());
+                let (status, _) = send(request).await?;
+                assert_eq!(status, StatusCode::PayloadTooLarge);
+                let mut request = Request::new(Method::Post, url("/get"));
+                request.set_body(Body::from_reader(async_std::io::Cursor::new(body.into_bytes()), None));
+                let (status, _) = send(request).await?;
+                assert_eq!(status, StatusCode::PayloadTooLarge);
+
+                // unknown path
+                let (status, _) = send(Request::new(Method::Get, url("/values"))).await?;
+                assert_eq!(status, StatusCode::NotFound);
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+}
@@ -1,5 +1,19 @@
 pub mod common;
 pub mod utils;
+pub mod error;
 pub mod message;
 pub mod message_receiver;
 pub mod connection;
+pub mod alarm;
+pub mod store;
+pub mod permission;
+pub mod auth;
+pub mod tls;
+pub mod dispatch;
+pub mod websocket;
+pub mod http;
+pub mod udp;
+pub mod codec;
+pub mod heartbeat;
+pub mod client;
+pub mod blocking_client;
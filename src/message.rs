@@ -1,12 +1,65 @@
 use crate::common::{Label, Value};
+use crate::alarm::{ActiveAlarm, AlarmLevel};
+use crate::codec::Codec;
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Version of the protocol spoken by this crate. Peers announce theirs in
+/// `HelloRequest`.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version the server still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Outcome of a request.
+///
+/// Since protocol version 1 every status is sent as the variant name, for
+/// example `"NotFound"`. Names are never changed within a protocol version;
+/// new codes are only added. A code the receiver does not know is read as
+/// `Unknown`, so a newer peer's codes do not fail the whole message. The
+/// SNAKE_CASE spelling of each code, for example `"NOT_FOUND"`, is accepted
+/// on input as an alias.
+///
+/// | Status         | Alias             | Meaning                                   |
+/// |----------------|-------------------|-------------------------------------------|
+/// | OK             | OK                | the request succeeded                     |
+/// | InvalidRequest | INVALID_REQUEST   | the request could not be understood       |
+/// | NotFound       | NOT_FOUND         | a label or alarm does not exist           |
+/// | Forbidden      | FORBIDDEN         | the client may not do this                |
+/// | Unauthorized   | UNAUTHORIZED      | the client has not logged in              |
+/// | Conflict       | CONFLICT          | the request conflicts with current state  |
+/// | TypeMismatch   | TYPE_MISMATCH     | a value has the wrong type for its label  |
+/// | OutOfRange     | OUT_OF_RANGE      | a value is outside the allowed range      |
+/// | ReadOnly       | READ_ONLY         | the label cannot be written               |
+/// | Busy           | BUSY              | the server cannot take the request now    |
+/// | RateLimited    | RATE_LIMITED      | the client sends requests too fast        |
+/// | InternalError  | INTERNAL_ERROR    | the server failed to handle the request   |
+/// | Unknown        |                   | a code from a newer peer; never sent      |
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
 pub enum Status {
     OK,
+    #[serde(alias = "INVALID_REQUEST")]
     InvalidRequest,
-    // coding idea memo #[serde(rename = "NOT_FOUND")]
+    #[serde(alias = "NOT_FOUND")]
     NotFound,
+    #[serde(alias = "FORBIDDEN")]
+    Forbidden,
+    #[serde(alias = "UNAUTHORIZED")]
+    Unauthorized,
+    #[serde(alias = "CONFLICT")]
+    Conflict,
+    #[serde(alias = "TYPE_MISMATCH")]
+    TypeMismatch,
+    #[serde(alias = "OUT_OF_RANGE")]
+    OutOfRange,
+    #[serde(alias = "READ_ONLY")]
+    ReadOnly,
+    #[serde(alias = "BUSY")]
+    Busy,
+    #[serde(alias = "RATE_LIMITED")]
+    RateLimited,
+    #[serde(alias = "INTERNAL_ERROR")]
+    InternalError,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -16,6 +69,93 @@ pub enum Message {
     GetDataResponse(GetDataResponse),
     SetDataRequest(SetDataRequest),
     SetDataResponse(SetDataResponse),
+    SubscribeAlarmRequest(SubscribeAlarmRequest),
+    SubscribeAlarmResponse(SubscribeAlarmResponse),
+    GetAlarmRequest(GetAlarmRequest),
+    GetAlarmResponse(GetAlarmResponse),
+    AckAlarmRequest(AckAlarmRequest),
+    AckAlarmResponse(AckAlarmResponse),
+    AlarmNotification(AlarmNotification),
+    LoginRequest(LoginRequest),
+    LoginResponse(LoginResponse),
+    HelloRequest(HelloRequest),
+    HelloResponse(HelloResponse),
+    Ping(Ping),
+    Pong(Pong),
+    ErrorResponse(ErrorResponse),
+}
+
+impl Message {
+    /// Name of the command as it appears in the `command` field.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Message::GetDataRequest(..) => "GetDataRequest",
+            Message::GetDataResponse(..) => "GetDataResponse",
+            Message::SetDataRequest(..) => "SetDataRequest",
+            Message::SetDataResponse(..) => "SetDataResponse",
+            Message::SubscribeAlarmRequest(..) => "SubscribeAlarmRequest",
+            Message::SubscribeAlarmResponse(..) => "SubscribeAlarmResponse",
+            Message::GetAlarmRequest(..) => "GetAlarmRequest",
+            Message::GetAlarmResponse(..) => "GetAlarmResponse",
+            Message::AckAlarmRequest(..) => "AckAlarmRequest",
+            Message::AckAlarmResponse(..) => "AckAlarmResponse",
+            Message::AlarmNotification(..) => "AlarmNotification",
+            Message::LoginRequest(..) => "LoginRequest",
+            Message::LoginResponse(..) => "LoginResponse",
+            Message::HelloRequest(..) => "HelloRequest",
+            Message::HelloResponse(..) => "HelloResponse",
+            Message::Ping(..) => "Ping",
+            Message::Pong(..) => "Pong",
+            Message::ErrorResponse(..) => "ErrorResponse",
+        }
+    }
+
+    pub fn tag(&self) -> Option<&String> {
+        match self {
+            Message::GetDataRequest(GetDataRequest{ tag, .. }) |
+            Message::GetDataResponse(GetDataResponse{ tag, .. }) |
+            Message::SetDataRequest(SetDataRequest{ tag, .. }) |
+            Message::SetDataResponse(SetDataResponse{ tag, .. }) |
+            Message::SubscribeAlarmRequest(SubscribeAlarmRequest{ tag, .. }) |
+            Message::SubscribeAlarmResponse(SubscribeAlarmResponse{ tag, .. }) |
+            Message::GetAlarmRequest(GetAlarmRequest{ tag, .. }) |
+            Message::GetAlarmResponse(GetAlarmResponse{ tag, .. }) |
+            Message::AckAlarmRequest(AckAlarmRequest{ tag, .. }) |
+            Message::AckAlarmResponse(AckAlarmResponse{ tag, .. }) |
+            Message::LoginRequest(LoginRequest{ tag, .. }) |
+            Message::LoginResponse(LoginResponse{ tag, .. }) |
+            Message::HelloRequest(HelloRequest{ tag, .. }) |
+            Message::HelloResponse(HelloResponse{ tag, .. }) |
+            Message::Ping(Ping{ tag, .. }) |
+            Message::Pong(Pong{ tag, .. }) |
+            Message::ErrorResponse(ErrorResponse{ tag, .. }) => tag.as_ref(),
+            Message::AlarmNotification(..) => None,
+        }
+    }
+
+    /// Lets a client tag a request so the response can be matched to it.
+    pub fn tag_mut(&mut self) -> Option<&mut Option<String>> {
+        match self {
+            Message::GetDataRequest(GetDataRequest { tag, .. }) |
+            Message::GetDataResponse(GetDataResponse { tag, .. }) |
+            Message::SetDataRequest(SetDataRequest { tag, .. }) |
+            Message::SetDataResponse(SetDataResponse { tag, .. }) |
+            Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag, .. }) |
+            Message::SubscribeAlarmResponse(SubscribeAlarmResponse { tag, .. }) |
+            Message::GetAlarmRequest(GetAlarmRequest { tag, .. }) |
+            Message::GetAlarmResponse(GetAlarmResponse { tag, .. }) |
+            Message::AckAlarmRequest(AckAlarmRequest { tag, .. }) |
+            Message::AckAlarmResponse(AckAlarmResponse { tag, .. }) |
+            Message::LoginRequest(LoginRequest { tag, .. }) |
+            Message::LoginResponse(LoginResponse { tag, .. }) |
+            Message::HelloRequest(HelloRequest { tag, .. }) |
+            Message::HelloResponse(HelloResponse { tag, .. }) |
+            Message::Ping(Ping { tag, .. }) |
+            Message::Pong(Pong { tag, .. }) |
+            Message::ErrorResponse(ErrorResponse { tag, .. }) => Some(tag),
+            Message::AlarmNotification(..) => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -45,15 +185,164 @@ pub struct SetDataResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tag: Option<String>,
     pub status: Status,
+    /// Outcome for each requested label, in request order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<LabeledValue>,
 }
 
+/// In responses, `status` and `error` tell the outcome for this label, so a
+/// missing label is not confused with a stored Null.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct LabeledValue {
     pub label: Label,
     pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl LabeledValue {
+    pub fn new(label: Label, value: Value) -> Self {
+        Self { label, value, status: None, error: None }
+    }
+
+    /// Result entry for a response.
+    pub fn result(label: Label, value: Value, status: Status, error: Option<String>) -> Self {
+        Self { label, value, status: Some(status), error }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SubscribeAlarmRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SubscribeAlarmResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GetAlarmRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GetAlarmResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+    pub results: Vec<ActiveAlarm>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AckAlarmRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub params: Vec<Label>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AckAlarmResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum AlarmState {
+    Active,
+    Cleared,
+}
+
+/// Pushed to alarm subscribers whenever the alarm level of a label changes.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AlarmNotification {
+    pub label: Label,
+    pub state: AlarmState,
+    pub level: AlarmLevel,
+    pub value: Value,
+}
+
+/// Either `token` or `username` and `password` identify the client.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LoginRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LoginResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+}
+
+/// First message of a connection, announcing what the client speaks.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HelloRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub version: u32,
+    pub client_name: String,
+    #[serde(default)]
+    pub codecs: Vec<Codec>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// Carries the protocol version, codec and features the server accepted.
+/// When the status is not OK the server closes the connection; `version` is
+/// then the one the server speaks and `reason` says why.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HelloResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+    pub version: u32,
+    pub codec: Codec,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Heartbeat sent by clients to keep an otherwise idle connection alive.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Ping {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Pong {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// Sent when a request cannot be answered with its own response, for
+/// example because it could not be read or is not a supported command.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ErrorResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub status: Status,
+    pub reason: String,
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use crate::message::*;
 
@@ -61,10 +350,7 @@ mod tests {
     fn test_serialize_set_request() {
         let message = Message::SetDataRequest(SetDataRequest {
             tag: Some("ABC".to_string()),
-            params: vec![LabeledValue {
-                label: "SP1".to_string(),
-                value: Value::Float(3.0),
-            }],
+            params: vec![LabeledValue::new("SP1".to_string(), Value::Float(3.0))],
         });
 
         let json = serde_json::to_string(&message).unwrap();
@@ -98,10 +384,7 @@ mod tests {
     fn test_serialize_no_tagged_request() {
         let message = Message::SetDataRequest(SetDataRequest {
             tag: None,
-            params: vec![LabeledValue {
-                label: "SP1".to_string(),
-                value: Value::Float(3.0),
-            }],
+            params: vec![LabeledValue::new("SP1".to_string(), Value::Float(3.0))],
         });
 
         let json = serde_json::to_string(&message).unwrap();
@@ -113,10 +396,7 @@ mod tests {
     fn test_serialize_null_value_request() {
         let message = Message::SetDataRequest(SetDataRequest {
             tag: None,
-            params: vec![LabeledValue {
-                label: "SP1".to_string(),
-                value: Value::Null,
-            }],
+            params: vec![LabeledValue::new("SP1".to_string(), Value::Null)],
         });
 
         let json = serde_json::to_string(&message).unwrap();
@@ -204,10 +484,7 @@ mod tests {
         let message = Message::GetDataResponse(GetDataResponse {
             tag: None,
             status: Status::OK,
-            results: vec![LabeledValue {
-                label: "SP1".to_string(),
-                value: Value::Float(3.0),
-            }],
+            results: vec![LabeledValue::new("SP1".to_string(), Value::Float(3.0))],
         });
 
         let json = serde_json::to_string(&message).unwrap();
@@ -264,6 +541,7 @@ mod tests {
         let message = Message::SetDataResponse(SetDataResponse {
             tag: None,
             status: Status::OK,
+            results: vec![],
         });
 
         let json = serde_json::to_string(&message).unwrap();
@@ -289,4 +567,94 @@ mod tests {
             panic!("not SetDataResponse");
         }
     }
+
+    #[test]
+    fn test_serialize_alarm_notification() {
+        let message = Message::AlarmNotification(AlarmNotification {
+            label: "TI1".to_string(),
+            state: AlarmState::Active,
+            level: AlarmLevel::HighHigh,
+            value: Value::Float(95.5),
+        });
+
+        let json = serde_json::to_string(&message).unwrap();
+
+        assert_eq!(json,
+            r#"{"command":"AlarmNotification","label":"TI1","state":"Active","level":"HighHigh","value":95.5}"#);
+    }
+
+    #[test]
+    fn test_deserialize_ack_alarm_request() {
+        let json = r#"
+            {
+                "command": "AckAlarmRequest",
+                "tag": "123",
+                "params": ["TI1"]
+            }
+        "#;
+
+        if let Message::AckAlarmRequest(message) = serde_json::from_str(json).unwrap() {
+            assert_eq!(message.tag, Some("123".to_string()));
+            assert_eq!(message.params[0], "TI1");
+        } else {
+            panic!("not AckAlarmRequest");
+        }
+    }
+
+    #[test]
+    fn test_deserialize_hello_request() {
+        let json = r#"
+            {
+                "command": "HelloRequest",
+                "version": 1,
+                "client_name": "hmi1",
+                "codecs": ["Json", "MessagePack"]
+            }
+        "#;
+
+        if let Message::HelloRequest(message) = serde_json::from_str(json).unwrap() {
+            assert_eq!(message.version, 1);
+            assert_eq!(message.client_name, "hmi1");
+            assert_eq!(message.codecs, vec![Codec::Json, Codec::MessagePack]);
+            assert!(message.features.is_empty());
+        } else {
+            panic!("not HelloRequest");
+        }
+    }
+
+    #[test]
+    fn test_serialize_ping() {
+        let message = Message::Ping(Ping { tag: None });
+
+        let json = serde_json::to_string(&message).unwrap();
+
+        assert_eq!(json, r#"{"command":"Ping"}"#);
+    }
+
+    #[test]
+    fn test_status_wire_names() {
+        assert_eq!(serde_json::to_string(&Status::NotFound).unwrap(), r#""NotFound""#);
+        assert_eq!(serde_json::to_string(&Status::OutOfRange).unwrap(), r#""OutOfRange""#);
+
+        let status: Status = serde_json::from_str(r#""NOT_FOUND""#).unwrap();
+        assert_eq!(status, Status::NotFound);
+        let status: Status = serde_json::from_str(r#""RATE_LIMITED""#).unwrap();
+        assert_eq!(status, Status::RateLimited);
+        let status: Status = serde_json::from_str(r#""InternalError""#).unwrap();
+        assert_eq!(status, Status::InternalError);
+    }
+
+    #[test]
+    fn test_deserialize_unknown_status() {
+        let json = r#"{"command":"SetDataResponse","status":"Throttled"}"#;
+        if let Message::SetDataResponse(r) = serde_json::from_str(json).unwrap() {
+            assert_eq!(r.status, Status::Unknown);
+        } else {
+            panic!("not SetDataResponse");
+        }
+
+        let bytes = rmp_serde::to_vec_named(&"Throttled").unwrap();
+        let status: Status = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(status, Status::Unknown);
+    }
 }
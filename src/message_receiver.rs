@@ -1,53 +1,219 @@
-use crate::utils::{self, AppResult};
+use crate::utils::{self, AppError, AppResult, FrameTooLarge, MalformedMessage};
 use crate::message::Message;
+use crate::codec::{Codec, Format, Framing};
 use async_std::prelude::*;
 use async_std::io::BufReader;
-use std::cell::RefCell;
-use std::rc::Rc;
+use serde::Deserialize;
+use async_std::channel::{self, Receiver, Sender};
+use async_std::task::{self, JoinHandle};
+use std::pin::Pin;
+use std::sync::Arc;
 
+/// Frame waiting for the writer task, with the channel its result goes to.
+type Pending = (Vec<u8>, Sender<std::io::Result<()>>);
+
+/// Frames that may wait for the writer task. Past this, `send` waits and
+/// `queue` fails, so a client that stopped reading cannot use up memory.
+const QUEUED_FRAMES: usize = 64;
+
+/// Sends messages to one client. Clones share a writer task that owns the
+/// stream, so every message is written whole even when several tasks send
+/// at once.
 #[derive(Debug, Clone)]
-pub struct Outbound<S>(Rc<RefCell<S>>);
+pub struct Outbound {
+    frames: Sender<Pending>,
+    format: Format,
+    /// False when the transport frames messages itself.
+    framed: bool,
+    _writer: Arc<Writer>,
+}
 
-impl<S> Outbound<S>
-where
-    S: async_std::io::Write + std::marker::Unpin,
-{
-    pub fn new(to_client: S) -> Self {
-        Self(Rc::new(RefCell::new(to_client)))
+/// Cancels the writer task when the last `Outbound` is dropped, so a client
+/// that stopped reading cannot keep the task and its stream alive.
+#[derive(Debug)]
+struct Writer(Option<JoinHandle<()>>);
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Some(writer) = self.0.take() {
+            task::spawn(writer.cancel());
+        }
+    }
+}
+
+impl Outbound {
+    pub fn new<S>(to_client: S) -> Self
+    where
+        S: async_std::io::Write + std::marker::Unpin + Send + 'static,
+    {
+        Self::with_format(to_client, Format::default())
+    }
+
+    /// Spawns the writer task. It ends when the last clone is dropped, even
+    /// in the middle of a write, or when a write fails.
+    pub fn with_format<S>(mut to_client: S, format: Format) -> Self
+    where
+        S: async_std::io::Write + std::marker::Unpin + Send + 'static,
+    {
+        let (frames, pending) = channel::bounded::<Pending>(QUEUED_FRAMES);
+        let writer = task::spawn(async move {
+            while let Ok((frame, written)) = pending.recv().await {
+                let result = match to_client.write_all(&frame).await {
+                    Ok(()) => to_client.flush().await,
+                    Err(e) => Err(e),
+                };
+                let failed = result.is_err();
+                let _ = written.send(result).await;
+                if failed {
+                    break;
+                }
+            }
+        });
+        Self { frames, format, framed: true, _writer: Arc::new(Writer(Some(writer))) }
     }
 
+    /// Hands every message over on the returned channel, encoded but not
+    /// framed, for transports with frames of their own such as WebSocket.
+    pub fn channel(codec: Codec) -> (Self, Receiver<Vec<u8>>) {
+        let (to_transport, from_outbound) = channel::bounded(1);
+        let (frames, pending) = channel::bounded::<Pending>(QUEUED_FRAMES);
+        let writer = task::spawn(async move {
+            while let Ok((frame, written)) = pending.recv().await {
+                let result = to_transport.send(frame).await
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+                let failed = result.is_err();
+                let _ = written.send(result).await;
+                if failed {
+                    break;
+                }
+            }
+        });
+        let outbound = Self { frames, format: Format::new(codec), framed: false, _writer: Arc::new(Writer(Some(writer))) };
+        (outbound, from_outbound)
+    }
+
+    /// Returns once the message has been written and flushed.
     pub async fn send(&self, message: &Message) -> AppResult<()> {
-        let mut outbound = self.0.borrow_mut();
-        utils::send_as_json(&mut *outbound, message).await?;
-        outbound.flush().await?;
-        Ok(())
+        let (written, result) = channel::bounded(1);
+        self.frames.send((self.frame(message)?, written)).await.map_err(|_| closed())?;
+        Ok(result.recv().await.map_err(|_| closed())??)
+    }
+
+    /// Queues the message without waiting for it to be written, for callers
+    /// that cannot wait. Fails once the writer has stopped or when
+    /// `QUEUED_FRAMES` are already waiting.
+    pub fn queue(&self, message: &Message) -> AppResult<()> {
+        let (written, _) = channel::bounded(1);
+        self.frames.try_send((self.frame(message)?, written)).map_err(|_| closed())
     }
+
+    fn frame(&self, message: &Message) -> AppResult<Vec<u8>> {
+        let mut frame = self.format.codec.encode(message)?;
+        match self.format.framing {
+            _ if !self.framed => (),
+            Framing::Lines => frame.push(b'\n'),
+            Framing::LengthPrefixed => {
+                let len = u32::try_from(frame.len()).map_err(|_| FrameTooLarge { limit: u32::MAX as usize })?;
+                frame.splice(0..0, len.to_be_bytes());
+            }
+        }
+        Ok(frame)
+    }
+}
+
+fn closed() -> AppError {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "connection writer has stopped").into()
 }
 
-pub fn receive_message<T>(async_io: T) -> impl Stream<Item = AppResult<(Message, Outbound<T>)>>
+pub fn receive_message<T>(async_io: T) -> impl Stream<Item = AppResult<(Message, Outbound)>>
 where
-    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone,
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
 {
-    let outbound = Outbound::new(async_io.clone());
-    utils::receive_as_json(BufReader::new(async_io))
-        .map(move |message_result| {
-            let message: Message = message_result?;
-            Ok((message, outbound.clone()))
-        })
+    receive_message_with(async_io, Format::default())
+}
+
+pub fn receive_message_with<T>(async_io: T, format: Format)
+    -> impl Stream<Item = AppResult<(Message, Outbound)>>
+where
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin + std::clone::Clone + Send + 'static,
+{
+    let outbound = Outbound::with_format(async_io.clone(), format);
+    receive_messages(async_io, format).map(move |message_result| {
+        let message: Message = message_result?;
+        Ok((message, outbound.clone()))
+    })
+}
+
+pub fn receive_messages<T>(async_io: T, format: Format) -> Pin<Box<dyn Stream<Item = AppResult<Message>> + Send>>
+where
+    T: async_std::io::Read + std::marker::Unpin + Send + 'static,
+{
+    let codec = format.codec;
+    match format.framing {
+        Framing::Lines => Box::pin(utils::receive_lines(BufReader::new(async_io), format.max_frame_size)
+            .map(move |line_result| decode_message(codec, &line_result?))),
+        Framing::LengthPrefixed => Box::pin(utils::receive_frames(async_io, format.max_frame_size)
+            .map(move |frame_result| decode_message(codec, &frame_result?))),
+    }
+}
+
+/// Decodes one frame, reporting undecodable input as `MalformedMessage`.
+pub fn decode_message(codec: Codec, frame: &[u8]) -> AppResult<Message> {
+    #[derive(Deserialize)]
+    struct Tagged {
+        #[serde(default)]
+        tag: Option<String>,
+    }
+
+    codec.decode(frame).map_err(|e| match e {
+        AppError::Decode(MalformedMessage { reason, .. }) => {
+            let tag = codec.decode::<Tagged>(frame).ok().and_then(|tagged| tagged.tag);
+            MalformedMessage { tag, reason }.into()
+        }
+        e => e,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use async_std::task;
-    use async_std::io::Cursor;
+    use crate::message::*;
+    use async_std::io::{self, Cursor};
+    use async_std::task::{Context, Poll};
+    use std::time::Duration;
+    use std::sync::Mutex;
+
+    /// Collects everything written to it.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Sink {
+        fn contents(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl io::Write for Sink {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
 
     #[test]
     fn test_message_receive() {
         let test_message = format!("{}\n{}\n",
             r#"{"command":"GetDataRequest","params":["SP1"]}"#,
             r#"{"command":"SetDataRequest","params":[{"label":"SP1","value":34.5}]}"#);
-        let buf: Vec<u8> = test_message.as_bytes().iter().map(|b| b.clone()).collect();
+        let buf: Vec<u8> = test_message.as_bytes().to_vec();
         let cursor = Cursor::new(buf);
 
         task::block_on(async {
@@ -68,4 +234,140 @@ mod tests {
             assert!(matches!(result, Ok(..)));
         });
     }
+
+    #[test]
+    fn test_message_receive_with_message_pack() {
+        let message = Message::GetDataRequest(GetDataRequest {
+            tag: None,
+            params: vec!["SP1".to_string()],
+        });
+
+        task::block_on(async {
+            let sink = Sink::default();
+            Outbound::with_format(sink.clone(), Format::new(Codec::MessagePack)).send(&message).await.unwrap();
+            let cursor = Cursor::new(sink.contents());
+
+            let mut receiver = receive_message_with(cursor, Format::new(Codec::MessagePack));
+            let (received, _) = receiver.next().await.unwrap().unwrap();
+            assert_eq!(received, message);
+            assert!(receiver.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_message_receive_json_length_prefixed() {
+        let format = Format::new(Codec::Json).framing(Framing::LengthPrefixed);
+        let message = Message::GetDataRequest(GetDataRequest {
+            tag: Some("multi\nline".to_string()),
+            params: vec!["SP1".to_string()],
+        });
+
+        task::block_on(async {
+            let sink = Sink::default();
+            Outbound::with_format(sink.clone(), format).send(&message).await.unwrap();
+            let cursor = Cursor::new(sink.contents());
+
+            let mut receiver = receive_message_with(cursor, format);
+            let (received, _) = receiver.next().await.unwrap().unwrap();
+            assert_eq!(received, message);
+        });
+    }
+
+    #[test]
+    fn test_decode_malformed_message() {
+        let error = decode_message(Codec::Json, br#"{"command":"Reboot","tag":"ABC"}"#).unwrap_err();
+        assert!(matches!(error, AppError::Decode(MalformedMessage { tag: Some(tag), .. }) if tag == "ABC"));
+
+        let error = decode_message(Codec::Json, b"{not json").unwrap_err();
+        assert!(matches!(error, AppError::Decode(MalformedMessage { tag: None, .. })));
+    }
+
+    #[test]
+    fn test_outbound_from_many_tasks() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        task::block_on(async {
+            let sink = Sink::default();
+            let outbound = Outbound::new(sink.clone());
+            assert_send_sync(&outbound);
+
+            let senders: Vec<_> = (0..10).map(|i| {
+                let outbound = outbound.clone();
+                task::spawn(async move {
+                    let message = Message::Ping(Ping { tag: Some(i.to_string().repeat(1000)) });
+                    outbound.send(&message).await
+                })
+            }).collect();
+            for sender in senders {
+                sender.await.unwrap();
+            }
+
+            let mut receiver = receive_messages(Cursor::new(sink.contents()), Format::default());
+            let mut count = 0;
+            while let Some(message) = receiver.next().await {
+                assert!(matches!(message.unwrap(), Message::Ping(..)));
+                count += 1;
+            }
+            assert_eq!(count, 10);
+        });
+    }
+
+    #[test]
+    fn test_outbound_channel() {
+        task::block_on(async {
+            let (outbound, queued) = Outbound::channel(Codec::Json);
+            let message = Message::Ping(Ping { tag: Some("A".to_string()) });
+
+            outbound.queue(&message).unwrap();
+            assert_eq!(queued.recv().await.unwrap(), br#"{"command":"Ping","tag":"A"}"#.to_vec());
+
+            drop(queued);
+            assert!(outbound.send(&message).await.is_err());
+        });
+    }
+
+    /// Never accepts a byte, like a client that stopped reading.
+    struct Stalled {
+        _stream: Arc<()>,
+    }
+
+    impl io::Write for Stalled {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8]) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_outbound_queue_is_bounded() {
+        task::block_on(async {
+            let outbound = Outbound::new(Stalled { _stream: Arc::new(()) });
+            let message = Message::Ping(Ping { tag: None });
+
+            let queued = (0..QUEUED_FRAMES + 2).take_while(|_| outbound.queue(&message).is_ok()).count();
+            assert!(queued <= QUEUED_FRAMES + 1);
+        });
+    }
+
+    #[test]
+    fn test_outbound_drop_releases_stalled_stream() {
+        task::block_on(async {
+            let stream = Arc::new(());
+            let outbound = Outbound::new(Stalled { _stream: stream.clone() });
+
+            let message = Message::Ping(Ping { tag: None });
+            assert!(outbound.send(&message).timeout(Duration::from_millis(50)).await.is_err());
+            drop(outbound);
+
+            task::sleep(Duration::from_millis(50)).await;
+            assert_eq!(Arc::strong_count(&stream), 1);
+        });
+    }
 }
@@ -0,0 +1,96 @@
+use crate::common::Label;
+use crate::message::Status;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+/// Who a client is once it has been identified. Anonymous clients have none.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct ClientIdentity {
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Write access of a label. Labels without an entry are writable by anyone.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum WriteAccess {
+    ReadOnly,
+    Restricted {
+        #[serde(default)]
+        clients: Vec<String>,
+        #[serde(default)]
+        roles: Vec<String>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Permissions {
+    rules: HashMap<Label, WriteAccess>,
+}
+
+impl Permissions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_access(&mut self, label: Label, access: WriteAccess) {
+        self.rules.insert(label, access);
+    }
+
+    pub fn can_write(&self, label: &str, client: Option<&ClientIdentity>) -> bool {
+        self.write_status(label, client) == Status::OK
+    }
+
+    /// Status of a write to `label` by `client`: `ReadOnly` when nobody may
+    /// write it, `Forbidden` when only this client may not.
+    pub fn write_status(&self, label: &str, client: Option<&ClientIdentity>) -> Status {
+        match self.rules.get(label) {
+            None => Status::OK,
+            Some(WriteAccess::ReadOnly) => Status::ReadOnly,
+            Some(WriteAccess::Restricted { clients, roles }) => {
+                let allowed = client.is_some_and(|c| {
+                    clients.contains(&c.name) || c.roles.iter().any(|role| roles.contains(role))
+                });
+                if allowed { Status::OK } else { Status::Forbidden }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_write() {
+        let mut permissions = Permissions::new();
+        permissions.set_access("SP1".to_string(), WriteAccess::ReadOnly);
+        permissions.set_access("SP2".to_string(), WriteAccess::Restricted {
+            clients: vec!["hmi1".to_string()],
+            roles: vec!["operator".to_string()],
+        });
+
+        let hmi1 = ClientIdentity { name: "hmi1".to_string(), roles: vec![] };
+        let operator = ClientIdentity { name: "hmi2".to_string(), roles: vec!["operator".to_string()] };
+        let viewer = ClientIdentity { name: "hmi3".to_string(), roles: vec!["viewer".to_string()] };
+
+        assert!(permissions.can_write("PV1", None));
+        assert!(!permissions.can_write("SP1", Some(&hmi1)));
+        assert!(permissions.can_write("SP2", Some(&hmi1)));
+        assert!(permissions.can_write("SP2", Some(&operator)));
+        assert!(!permissions.can_write("SP2", Some(&viewer)));
+        assert!(!permissions.can_write("SP2", None));
+    }
+
+    #[test]
+    fn test_deserialize_write_access() {
+        let json = r#"{"Restricted": {"roles": ["operator"]}}"#;
+
+        let access: WriteAccess = serde_json::from_str(json).unwrap();
+
+        assert_eq!(access, WriteAccess::Restricted {
+            clients: vec![],
+            roles: vec!["operator".to_string()],
+        });
+    }
+}
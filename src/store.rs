@@ -0,0 +1,57 @@
+use crate::common::{Label, Value};
+use crate::alarm::AlarmTable;
+use crate::permission::Permissions;
+use crate::auth::Credentials;
+use crate::message::{AlarmNotification, Message};
+use crate::message_receiver::Outbound;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Store {
+    pub values: HashMap<Label, Value>,
+    pub alarms: AlarmTable,
+    pub permissions: Permissions,
+    /// When set, clients must log in before any other request.
+    pub credentials: Option<Credentials>,
+    /// Connections that receive every alarm notification, by subscriber id.
+    subscribers: HashMap<u64, Outbound>,
+    next_subscriber: u64,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, label: &str) -> Option<&Value> {
+        self.values.get(label)
+    }
+
+    /// Stores the value and returns the alarm notification it caused, if any.
+    pub fn set(&mut self, label: Label, value: Value) -> Option<AlarmNotification> {
+        let notification = self.alarms.evaluate(&label, &value);
+        self.values.insert(label, value);
+        notification
+    }
+
+    /// Registers a connection for alarm notifications, whichever client or
+    /// transport caused them. Returns the id to unsubscribe with.
+    pub fn subscribe(&mut self, outbound: Outbound) -> u64 {
+        self.next_subscriber += 1;
+        self.subscribers.insert(self.next_subscriber, outbound);
+        self.next_subscriber
+    }
+
+    pub fn unsubscribe(&mut self, subscriber: u64) {
+        self.subscribers.remove(&subscriber);
+    }
+
+    /// Queues the notification for every subscriber but `except`. Subscribers
+    /// whose connection has closed are dropped.
+    pub fn broadcast(&mut self, notification: &AlarmNotification, except: Option<u64>) {
+        self.subscribers.retain(|subscriber, outbound| {
+            Some(*subscriber) == except
+                || outbound.queue(&Message::AlarmNotification(notification.clone())).is_ok()
+        });
+    }
+}
@@ -0,0 +1,53 @@
+use crate::utils::AppResult;
+use futures_rustls::TlsAcceptor;
+use futures_rustls::rustls::{RootCertStore, ServerConfig};
+use futures_rustls::rustls::crypto::ring;
+use futures_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use futures_rustls::rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls::server::WebPkiClientVerifier;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// PEM files used to accept TLS connections. When `client_ca` is set, clients
+/// must present a certificate signed by one of its authorities.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn new<P: Into<PathBuf>>(cert: P, key: P) -> Self {
+        Self { cert: cert.into(), key: key.into(), client_ca: None }
+    }
+
+    pub fn client_ca<P: Into<PathBuf>>(mut self, client_ca: P) -> Self {
+        self.client_ca = Some(client_ca.into());
+        self
+    }
+
+    pub fn acceptor(&self) -> AppResult<TlsAcceptor> {
+        let provider = Arc::new(ring::default_provider());
+        let certs = CertificateDer::pem_file_iter(&self.cert)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&self.key)?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(path)? {
+                    roots.add(cert?)?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
@@ -0,0 +1,127 @@
+use crate::utils::AppResult;
+use crate::message::{ErrorResponse, Message, Status};
+use crate::codec::Codec;
+use crate::dispatch::{dispatch, error_reply, Session};
+use crate::message_receiver::decode_message;
+use crate::store::Store;
+use async_std::net::UdpSocket;
+use std::io;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Applies `SetDataRequest`s received as UDP datagrams, one JSON `Message`
+/// per datagram. Other commands are not applied. When `acknowledge` is set,
+/// the `SetDataResponse` is sent back to the source address, or an
+/// `ErrorResponse` for a malformed datagram or another command. Errors about
+/// a single peer, such as one reported unreachable by ICMP, are logged and
+/// serving goes on; other socket errors end it.
+pub async fn serve_udp(socket: &UdpSocket, store: &mut Store, acknowledge: bool) -> AppResult<()> {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) if concerns_one_peer(&e) => {
+                log::warn!("udp receive failed: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let replies = match decode_message(Codec::Json, &buf[..len]) {
+            Ok(message @ Message::SetDataRequest(..)) => dispatch(message, &mut Session::new(), store),
+            Ok(other) => vec![Message::ErrorResponse(ErrorResponse {
+                tag: other.tag().cloned(),
+                status: Status::InvalidRequest,
+                reason: format!("{} is not accepted over UDP", other.command()),
+            })],
+            Err(e) => error_reply(&e).into_iter().collect(),
+        };
+        if acknowledge {
+            if let Some(response) = replies.first() {
+                match socket.send_to(serde_json::to_string(response)?.as_bytes(), peer).await {
+                    Ok(..) => (),
+                    Err(e) if concerns_one_peer(&e) => log::warn!("udp acknowledgement to {} failed: {}", peer, e),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+fn concerns_one_peer(error: &io::Error) -> bool {
+    matches!(error.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Value;
+    use crate::message::*;
+    use async_std::prelude::*;
+    use async_std::task;
+
+    #[test]
+    fn test_serve_udp() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let server_socket = UdpSocket::bind("127.0.0.1:8897").await.unwrap();
+
+            // server
+            let server_fut = serve_udp(&server_socket, &mut store, true);
+
+            // client
+            let client_fut = async {
+                let socket = UdpSocket::bind("127.0.0.1:0").await?;
+                socket.connect("127.0.0.1:8897").await?;
+
+                // malformed datagram is answered
+                socket.send(b"{\"command\":").await?;
+                let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+                let len = socket.recv(&mut buf).await?;
+                let message: Message = serde_json::from_slice(&buf[..len])?;
+                assert!(matches!(message, Message::ErrorResponse(..)));
+
+                // other commands are answered, not applied
+                let message = Message::GetDataRequest(GetDataRequest {
+                    tag: Some("GET".to_string()),
+                    params: vec!["SP1".to_string()],
+                });
+                socket.send(serde_json::to_string(&message)?.as_bytes()).await?;
+                let len = socket.recv(&mut buf).await?;
+                if let Message::ErrorResponse(r) = serde_json::from_slice(&buf[..len])? {
+                    assert_eq!(r.tag, Some("GET".to_string()));
+                    assert_eq!(r.status, Status::InvalidRequest);
+                } else {
+                    panic!("not ErrorResponse");
+                }
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: Some("UDP".to_string()),
+                    params: vec![
+                        LabeledValue::new("SP1".to_string(), Value::Float(3.0)),
+                    ],
+                });
+                socket.send(serde_json::to_string(&message)?.as_bytes()).await?;
+
+                // recv acknowledgement
+                let len = socket.recv(&mut buf).await?;
+                if let Message::SetDataResponse(r) = serde_json::from_slice(&buf[..len])? {
+                    assert_eq!(r.tag, Some("UDP".to_string()));
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+            assert_eq!(store.get("SP1"), Some(&Value::Float(3.0)));
+        });
+    }
+}
@@ -1,8 +1,13 @@
+use crate::codec::DEFAULT_MAX_FRAME_SIZE;
 use async_std::prelude::*;
+use async_std::io;
+use async_std::task::{Context, Poll};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
-pub type AppError = Box<dyn std::error::Error>;
+pub type AppError = crate::error::Error;
 pub type AppResult<T> = Result<T, AppError>;
 
 pub async fn send_as_json<S, P>(outbound: &mut S, packet: &P) -> AppResult<()>
@@ -10,37 +15,245 @@ where
     S: async_std::io::Write + std::marker::Unpin,
     P: Serialize,
 {
-    let mut json = serde_json::to_string(&packet)?;
+    let mut json = serde_json::to_string(&packet).map_err(|e| AppError::Encode(e.to_string()))?;
     json.push('\n');
     outbound.write_all(json.as_bytes()).await?;
     Ok(())
 }
 
+pub async fn send_as_frame<S>(outbound: &mut S, frame: &[u8]) -> AppResult<()>
+where
+    S: async_std::io::Write + std::marker::Unpin,
+{
+    let len = u32::try_from(frame.len()).map_err(|_| FrameTooLarge { limit: u32::MAX as usize })?;
+    outbound.write_all(&len.to_be_bytes()).await?;
+    outbound.write_all(frame).await?;
+    Ok(())
+}
+
+/// Reported for a frame or line longer than the receiver accepts. The
+/// oversized input has been skipped, so the stream can still be read.
+#[derive(Debug, PartialEq)]
+pub struct FrameTooLarge {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "frame exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// Reported for input that could not be decoded as a message. `tag` is
+/// recovered when the input still carried one.
+#[derive(Debug, PartialEq)]
+pub struct MalformedMessage {
+    pub tag: Option<String>,
+    pub reason: String,
+}
+
+impl std::fmt::Display for MalformedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed message: {}", self.reason)
+    }
+}
+
+impl std::error::Error for MalformedMessage {}
+
+/// Splits the input into frames that are each prefixed by their length as a
+/// big-endian u32. A frame longer than `max_frame_size` is skipped and
+/// reported as `FrameTooLarge`. The stream ends cleanly only when the input
+/// ends between frames; a read error or a truncated frame is reported.
+pub fn receive_frames<S>(inbound: S, max_frame_size: usize) -> impl Stream<Item = AppResult<Vec<u8>>> + std::marker::Unpin
+where
+    S: async_std::io::Read + std::marker::Unpin,
+{
+    Box::pin(futures_lite::stream::unfold(Some(inbound), move |inbound| async move {
+        let mut inbound = inbound?;
+        let mut len = [0u8; 4];
+        let read = loop {
+            match inbound.read(&mut len).await {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read,
+            }
+        };
+        let rest = match read {
+            Ok(0) => return None,
+            Ok(read) => inbound.read_exact(&mut len[read..]).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = rest {
+            return Some((Err(e.into()), None));
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > max_frame_size {
+            let skipped = io::copy(&mut (&mut inbound).take(len as u64), &mut io::sink()).await;
+            return match skipped {
+                Ok(..) => Some((Err(FrameTooLarge { limit: max_frame_size }.into()), Some(inbound))),
+                Err(e) => Some((Err(e.into()), None)),
+            };
+        }
+        let mut frame = vec![0u8; len];
+        match inbound.read_exact(&mut frame).await {
+            Ok(()) => Some((Ok(frame), Some(inbound))),
+            Err(e) => Some((Err(e.into()), None)),
+        }
+    }))
+}
+
+/// Splits the input into lines like `lines()`, but never buffers more than
+/// `max_line_length` bytes. A longer line is skipped up to its newline and
+/// reported as `FrameTooLarge`.
+pub fn receive_lines<S>(inbound: S, max_line_length: usize) -> impl Stream<Item = AppResult<Vec<u8>>> + std::marker::Unpin
+where
+    S: async_std::io::BufRead + std::marker::Unpin,
+{
+    // one byte over the limit tells a full line from one that is too long
+    let limit = max_line_length as u64 + 1;
+    Box::pin(futures_lite::stream::unfold(Some(inbound), move |inbound| async move {
+        let mut inbound = inbound?;
+        let mut line = Vec::new();
+        match (&mut inbound).take(limit).read_until(b'\n', &mut line).await {
+            Ok(0) => return None,
+            Ok(..) => (),
+            Err(e) => return Some((Err(e.into()), None)),
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if line.len() > max_line_length {
+            loop {
+                line.clear();
+                match (&mut inbound).take(limit).read_until(b'\n', &mut line).await {
+                    Ok(0) => break,
+                    Ok(..) if line.last() == Some(&b'\n') => break,
+                    Ok(..) => (),
+                    Err(e) => return Some((Err(e.into()), None)),
+                }
+            }
+            return Some((Err(FrameTooLarge { limit: max_line_length }.into()), Some(inbound)));
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some((Ok(line), Some(inbound)))
+    }))
+}
+
 pub fn receive_as_json<S, P>(inbound: S) -> impl Stream<Item = AppResult<P>>
 where
     S: async_std::io::BufRead + std::marker::Unpin,
     P: DeserializeOwned,
 {
-    inbound.lines()
+    receive_lines(inbound, DEFAULT_MAX_FRAME_SIZE)
         .map(|line_result| -> AppResult<P> {
             let line = line_result?;
-            let parsed = serde_json::from_str::<P>(&line)?;
+            let parsed = serde_json::from_slice::<P>(&line)?;
             Ok(parsed)
         })
 }
 
+/// Clonable handle to a stream that cannot be cloned itself, such as a TLS
+/// stream, so that it can be read and written through separate handles.
+#[derive(Debug)]
+pub struct SharedStream<S>(Arc<Mutex<S>>);
+
+impl<S> SharedStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+}
+
+impl<S> Clone for SharedStream<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S> io::Read for SharedStream<S>
+where
+    S: io::Read + std::marker::Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl<S> io::Write for SharedStream<S>
+where
+    S: io::Write + std::marker::Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_close(cx)
+    }
+}
+
+/// Joins a separate reader and writer, such as stdin and stdout, into one
+/// stream.
+#[derive(Debug)]
+pub struct Duplex<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> Duplex<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+}
+
+impl<R, W> io::Read for Duplex<R, W>
+where
+    R: io::Read + std::marker::Unpin,
+    W: std::marker::Unpin,
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl<R, W> io::Write for Duplex<R, W>
+where
+    R: std::marker::Unpin,
+    W: io::Write + std::marker::Unpin,
+{
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
 pub fn type_of<T>(_: T) -> &'static str {
     std::any::type_name::<T>()
 }
 
 #[cfg(test)]
-mod test {
+#[allow(clippy::approx_constant)]
+pub(crate) mod test {
     use crate::common::Value;
-    use crate::utils::AppResult;
+    use crate::utils::{AppError, AppResult};
     use crate::message::*;
     use async_std::prelude::*;
     use async_std::task;
     use async_std::io::{Cursor, BufReader};
+    use async_std::task::{Context, Poll};
+    use std::pin::Pin;
 
     #[test]
     fn test_send_as_json() {
@@ -88,7 +301,7 @@ mod test {
             assert_eq!(req.tag, Some("ABC".to_string()));
             assert_eq!(req.params[0], "SP1".to_string());
         } else {
-            assert!(false);
+            panic!("not GetDataRequest");
         }
 
         if let Message::SetDataRequest(req) = &messages[1] {
@@ -96,7 +309,133 @@ mod test {
             assert_eq!(req.params[0].label, "NE1".to_string());
             assert_eq!(req.params[0].value, Value::Float(-3.14));
         } else {
-            assert!(false);
+            panic!("not SetDataRequest");
         }
     }
+
+    #[test]
+    fn test_duplex() {
+        let output = task::block_on(async {
+            let input = format!("{}\n", r#"{"command":"GetDataRequest","params":["SP1"]}"#);
+            let mut duplex = super::Duplex::new(BufReader::new(input.as_bytes()), Cursor::new(Vec::new()));
+
+            let mut line = String::new();
+            duplex.reader.read_line(&mut line).await.unwrap();
+            let message: Message = serde_json::from_str(&line).unwrap();
+            super::send_as_json(&mut duplex, &message).await.unwrap();
+            String::from_utf8(duplex.writer.into_inner()).unwrap()
+        });
+
+        assert_eq!(output, format!("{}\n", r#"{"command":"GetDataRequest","params":["SP1"]}"#));
+    }
+
+    #[test]
+    fn test_send_and_receive_frames() {
+        let frames_result: AppResult<Vec<Vec<u8>>> = task::block_on(async {
+            let mut buf = Cursor::new(Vec::new());
+            super::send_as_frame(&mut buf, b"ABC").await?;
+            super::send_as_frame(&mut buf, b"").await?;
+            super::send_as_frame(&mut buf, b"\n\n").await?;
+
+            let input = buf.into_inner();
+            assert_eq!(&input[..7], &[0, 0, 0, 3, b'A', b'B', b'C']);
+            let mut frame_stream = super::receive_frames(input.as_slice(), 3);
+
+            let mut frames = Vec::new();
+            while let Some(frame) = frame_stream.next().await {
+                frames.push(frame?);
+            }
+            Ok(frames)
+        });
+
+        assert_eq!(frames_result.unwrap(), vec![b"ABC".to_vec(), vec![], b"\n\n".to_vec()]);
+    }
+
+    #[test]
+    fn test_receive_frames_over_limit() {
+        task::block_on(async {
+            let mut buf = Cursor::new(Vec::new());
+            super::send_as_frame(&mut buf, b"ABCD").await.unwrap();
+            super::send_as_frame(&mut buf, b"A").await.unwrap();
+
+            let input = buf.into_inner();
+            let mut frame_stream = super::receive_frames(input.as_slice(), 3);
+            let error = frame_stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(error, AppError::FrameTooLarge(super::FrameTooLarge { limit: 3 })));
+            assert_eq!(frame_stream.next().await.unwrap().unwrap(), b"A".to_vec());
+            assert!(frame_stream.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_receive_frames_truncated_header() {
+        task::block_on(async {
+            let input = [0u8, 0, 0, 1, b'A', 0, 0];
+            let mut frame_stream = super::receive_frames(&input[..], 3);
+            assert_eq!(frame_stream.next().await.unwrap().unwrap(), b"A".to_vec());
+            let error = frame_stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(error, AppError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+            assert!(frame_stream.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_receive_lines_over_limit() {
+        task::block_on(async {
+            let input = format!("{}\nABC\r\n{}\nDEF", "X".repeat(100), "Y".repeat(5));
+            // a small buffer makes the long line arrive in many pieces
+            let reader = BufReader::with_capacity(4, input.as_bytes());
+
+            let mut line_stream = super::receive_lines(reader, 4);
+            let error = line_stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(error, AppError::FrameTooLarge(super::FrameTooLarge { limit: 4 })));
+            assert_eq!(line_stream.next().await.unwrap().unwrap(), b"ABC".to_vec());
+            assert!(line_stream.next().await.unwrap().is_err());
+            assert_eq!(line_stream.next().await.unwrap().unwrap(), b"DEF".to_vec());
+            assert!(line_stream.next().await.is_none());
+        });
+    }
+
+    /// Reads like async-std `Stdin`: after the input, EOF and `Pending`
+    /// alternate.
+    pub(crate) struct StdinLike {
+        input: Cursor<Vec<u8>>,
+        pending: bool,
+    }
+
+    impl StdinLike {
+        pub(crate) fn new(input: &str) -> Self {
+            Self { input: Cursor::new(input.as_bytes().to_vec()), pending: false }
+        }
+    }
+
+    impl async_std::io::Read for StdinLike {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+            let this = &mut *self;
+            match Pin::new(&mut this.input).poll_read(cx, buf) {
+                Poll::Ready(Ok(0)) if this.pending => {
+                    this.pending = false;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                Poll::Ready(Ok(0)) => {
+                    this.pending = true;
+                    Poll::Ready(Ok(0))
+                }
+                other => other,
+            }
+        }
+    }
+
+    #[test]
+    fn test_receive_lines_until_eof() {
+        task::block_on(async {
+            let reader = BufReader::new(StdinLike::new("ABC\nDEF\n"));
+
+            let mut line_stream = super::receive_lines(reader, 4);
+            assert_eq!(line_stream.next().await.unwrap().unwrap(), b"ABC".to_vec());
+            assert_eq!(line_stream.next().await.unwrap().unwrap(), b"DEF".to_vec());
+            assert!(line_stream.next().await.is_none());
+        });
+    }
 }
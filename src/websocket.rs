@@ -0,0 +1,180 @@
+use crate::utils::{AppError, AppResult};
+use crate::codec::{Codec, DEFAULT_MAX_FRAME_SIZE};
+use crate::dispatch::{dispatch, error_reply, Session};
+use crate::message::{ErrorResponse, Message, Status};
+use crate::message_receiver::{decode_message, Outbound};
+use crate::store::Store;
+use async_std::prelude::*;
+use async_tungstenite::accept_async_with_config;
+use async_tungstenite::tungstenite::protocol::WebSocketConfig;
+use async_tungstenite::tungstenite::Message as Frame;
+
+/// Serves one WebSocket client. Every text frame carries one `Message` as
+/// JSON, in both directions. Alarm notifications caused by other clients
+/// are pushed as frames of their own. Messages over
+/// `DEFAULT_MAX_FRAME_SIZE` end the connection.
+pub async fn serve_websocket<T>(socket: T, store: &mut Store) -> AppResult<()>
+where
+    T: async_std::io::Write + async_std::io::Read + std::marker::Unpin,
+{
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(DEFAULT_MAX_FRAME_SIZE))
+        .max_frame_size(Some(DEFAULT_MAX_FRAME_SIZE));
+    let mut websocket = accept_async_with_config(socket, Some(config)).await?;
+    let (outbound, queued) = Outbound::channel(Codec::Json);
+    let mut session = Session::new();
+    session.outbound = Some(outbound);
+    let serving = async {
+        loop {
+            let next_frame = async { Ok(websocket.next().await) };
+            let next_queued = async { Err(queued.recv().await.ok()) };
+            let frame_result = match next_frame.race(next_queued).await {
+                Ok(Some(frame_result)) => frame_result,
+                Ok(None) => break,
+                Err(Some(json)) => {
+                    let json = String::from_utf8(json).map_err(|e| AppError::Encode(e.to_string()))?;
+                    websocket.send(Frame::text(json)).await?;
+                    continue;
+                }
+                Err(None) => break,
+            };
+            let replies = match frame_result? {
+                Frame::Text(text) => match decode_message(Codec::Json, text.as_bytes()) {
+                    Ok(message) => dispatch(message, &mut session, store),
+                    Err(e) => error_reply(&e).into_iter().collect(),
+                },
+                Frame::Binary(..) => vec![Message::ErrorResponse(ErrorResponse {
+                    tag: None,
+                    status: Status::InvalidRequest,
+                    reason: "messages must be sent as text frames".to_string(),
+                })],
+                Frame::Close(..) => break,
+                _ => continue,
+            };
+            for reply in replies {
+                websocket.send(Frame::text(serde_json::to_string(&reply)?)).await?;
+            }
+            if session.closed {
+                websocket.close(None).await?;
+                break;
+            }
+        }
+        Ok(())
+    };
+    let result = serving.await;
+    if let Some(subscriber) = session.subscriber {
+        store.unsubscribe(subscriber);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Value;
+    use crate::message::*;
+    use crate::alarm::AlarmRule;
+    use async_std::{net, task};
+    use async_tungstenite::client_async;
+
+    fn receive(frame: Frame) -> Message {
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_serve_websocket() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            store.alarms.set_rule("TI1".to_string(), AlarmRule {
+                high: Some(80.0),
+                ..Default::default()
+            });
+            let listener = net::TcpListener::bind("localhost:8895").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let mut new_connections = listener.incoming();
+                while let Some(socket_result) = new_connections.next().await {
+                    let socket = socket_result?;
+                    serve_websocket(socket, &mut store).await?;
+                }
+                Ok(()) as AppResult<()>
+            };
+
+            // client
+            let client_fut = async {
+                // connect
+                let socket = net::TcpStream::connect("localhost:8895").await?;
+                let (mut websocket, _) = client_async("ws://localhost:8895/", socket).await?;
+
+                // send SubscribeAlarmRequest
+                let message = Message::SubscribeAlarmRequest(SubscribeAlarmRequest { tag: None });
+                websocket.send(Frame::text(serde_json::to_string(&message)?)).await?;
+                let message = receive(websocket.next().await.unwrap()?);
+                assert!(matches!(message, Message::SubscribeAlarmResponse(..)));
+
+                // send SetDataRequest
+                let message = Message::SetDataRequest(SetDataRequest {
+                    tag: Some("WS".to_string()),
+                    params: vec![
+                        LabeledValue::new("TI1".to_string(), Value::Float(90.0)),
+                    ],
+                });
+                websocket.send(Frame::text(serde_json::to_string(&message)?)).await?;
+                let message = receive(websocket.next().await.unwrap()?);
+                if let Message::SetDataResponse(r) = message {
+                    assert_eq!(r.tag, Some("WS".to_string()));
+                    assert_eq!(r.status, Status::OK);
+                } else {
+                    panic!("not SetDataResponse");
+                }
+
+                // recv AlarmNotification pushed as its own frame
+                let message = receive(websocket.next().await.unwrap()?);
+                assert!(matches!(message, Message::AlarmNotification(..)));
+
+                // binary frames are answered, not dropped
+                websocket.send(Frame::binary(vec![0x80])).await?;
+                let message = receive(websocket.next().await.unwrap()?);
+                assert!(matches!(message, Message::ErrorResponse(ErrorResponse { status: Status::InvalidRequest, .. })));
+
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Ok(..)));
+        });
+    }
+
+    #[test]
+    fn test_websocket_message_over_limit() {
+
+        task::block_on(async {
+
+            let mut store = Store::new();
+            let listener = net::TcpListener::bind("localhost:8908").await.unwrap();
+
+            // server
+            let server_fut = async {
+                let (socket, _) = listener.accept().await.unwrap();
+                serve_websocket(socket, &mut store).await
+            };
+
+            // client
+            let client_fut = async {
+                let socket = net::TcpStream::connect("localhost:8908").await?;
+                let (mut websocket, _) = client_async("ws://localhost:8908/", socket).await?;
+                websocket.send(Frame::text("x".repeat(DEFAULT_MAX_FRAME_SIZE + 1))).await?;
+                // the server ends the connection instead of reading the message
+                while websocket.next().await.is_some_and(|frame| frame.is_ok()) {}
+                task::sleep(std::time::Duration::from_secs(5)).await;
+                Ok(()) as AppResult<()>
+            };
+
+            let result = server_fut.race(client_fut).await;
+            assert!(matches!(result, Err(AppError::Protocol(..))));
+        });
+    }
+}